@@ -0,0 +1,130 @@
+//! Classification of the bitcoin core RPC errors.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use bitcoincore_rpc::jsonrpc;
+use bitcoincore_rpc::jsonrpc::simple_http;
+
+use folgore_common::client::BackendKind;
+use folgore_common::errors::{ErrorKind, FolgoreError};
+
+use crate::transport::HttpsError;
+
+/// Invalid address or key, returned also when the
+/// block or the transaction is not found.
+const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+/// Bitcoin core return this code while it is still
+/// loading the block index.
+const RPC_IN_WARMUP: i32 = -28;
+/// The JSON RPC method does not exist.
+const RPC_METHOD_NOT_FOUND: i32 = -32601;
+
+/// Map the transport errors, the 5xx codes and the errors
+/// without a response (e.g. connection refused, timeout) are
+/// network errors, while the 4xx codes and the bodies that
+/// are not JSON RPC are not going to fix themselves, except
+/// the rate limit.
+fn transport_kind(err: &(dyn std::error::Error + Send + Sync + 'static)) -> ErrorKind {
+    let code = match (
+        err.downcast_ref::<simple_http::Error>(),
+        err.downcast_ref::<HttpsError>(),
+    ) {
+        (Some(simple_http::Error::HttpErrorCode(code)), _) => Some(*code),
+        (_, Some(HttpsError::Http(code))) => Some(*code),
+        // the server answered, but we are not able to decode
+        // the response, so retrying is not going to help.
+        (Some(simple_http::Error::Json(_)), _) | (_, Some(HttpsError::InvalidBody(_))) => {
+            return ErrorKind::InvalidResponse
+        }
+        _ => None,
+    };
+    match code {
+        Some(401 | 403) => ErrorKind::Auth,
+        Some(429) => ErrorKind::RateLimited,
+        // the url does not point to the bitcoin core RPC server
+        Some(404 | 405) => ErrorKind::Unsupported,
+        Some(400..=499) => ErrorKind::Validation,
+        _ => ErrorKind::Network,
+    }
+}
+
+/// Map the RPC error to a folgore error.
+pub fn rpc_error(err: bitcoincore_rpc::Error) -> FolgoreError {
+    let kind = match &err {
+        bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Transport(err)) => {
+            transport_kind(err.as_ref())
+        }
+        bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(err)) => match err.code {
+            RPC_IN_WARMUP => ErrorKind::Network,
+            RPC_INVALID_ADDRESS_OR_KEY => ErrorKind::NotFound,
+            RPC_METHOD_NOT_FOUND => ErrorKind::Unsupported,
+            _ => ErrorKind::Validation,
+        },
+        bitcoincore_rpc::Error::Io(_) => ErrorKind::Network,
+        _ => ErrorKind::InvalidResponse,
+    };
+    FolgoreError::new(kind, &err)
+        .with_backend(BackendKind::BitcoinCore)
+        .with_source(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoincore_rpc::jsonrpc;
+    use bitcoincore_rpc::jsonrpc::simple_http;
+
+    use folgore_common::errors::ErrorKind;
+    use folgore_common::stragegy::Transient;
+
+    use super::rpc_error;
+    use crate::transport::HttpsError;
+
+    fn transport<E: std::error::Error + Send + Sync + 'static>(err: E) -> bitcoincore_rpc::Error {
+        bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Transport(Box::new(err)))
+    }
+
+    fn rpc(code: i32) -> bitcoincore_rpc::Error {
+        bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(jsonrpc::error::RpcError {
+            code,
+            message: "error".to_owned(),
+            data: None,
+        }))
+    }
+
+    #[test]
+    fn test_transient_errors() {
+        let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        assert!(rpc_error(transport(simple_http::Error::SocketError(refused))).is_transient());
+        assert!(rpc_error(transport(simple_http::Error::HttpErrorCode(503))).is_transient());
+        assert!(rpc_error(transport(HttpsError::Http(502))).is_transient());
+        assert!(rpc_error(rpc(-28)).is_transient());
+        let err = rpc_error(transport(HttpsError::Http(429)));
+        assert_eq!(err.kind(), ErrorKind::RateLimited);
+        assert!(err.is_transient());
+    }
+
+    #[test]
+    fn test_permanent_errors() {
+        let err = rpc_error(transport(simple_http::Error::HttpErrorCode(401)));
+        assert_eq!(err.kind(), ErrorKind::Auth);
+        assert!(!err.is_transient());
+        let err = rpc_error(transport(HttpsError::Http(403)));
+        assert_eq!(err.kind(), ErrorKind::Auth);
+        for code in [404, 405] {
+            let err = rpc_error(transport(simple_http::Error::HttpErrorCode(code)));
+            assert_eq!(err.kind(), ErrorKind::Unsupported);
+            assert!(!err.is_transient());
+        }
+        let err = rpc_error(transport(HttpsError::Http(400)));
+        assert_eq!(err.kind(), ErrorKind::Validation);
+        assert!(!err.is_transient());
+        let err = rpc_error(transport(HttpsError::InvalidBody(200)));
+        assert_eq!(err.kind(), ErrorKind::InvalidResponse);
+        assert!(!err.is_transient());
+        // block not found
+        assert_eq!(rpc_error(rpc(-5)).kind(), ErrorKind::NotFound);
+        assert_eq!(rpc_error(rpc(-26)).kind(), ErrorKind::Validation);
+        let err = rpc_error(bitcoincore_rpc::Error::UnexpectedStructure);
+        assert_eq!(err.kind(), ErrorKind::InvalidResponse);
+        assert!(!err.is_transient());
+    }
+}
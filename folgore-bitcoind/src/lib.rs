@@ -5,186 +5,507 @@
 //!
 //! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
 #![deny(clippy::unwrap_used)]
+mod error;
+mod transport;
+
 use std::collections::BTreeMap;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
 use bitcoincore_rpc::bitcoin::consensus::{deserialize, serialize};
 use bitcoincore_rpc::bitcoin::secp256k1::serde::{Deserialize, Serialize};
+use bitcoincore_rpc::bitcoin::Amount;
 use bitcoincore_rpc::bitcoin::Transaction;
 use bitcoincore_rpc::bitcoin::Txid;
 use bitcoincore_rpc::bitcoincore_rpc_json::EstimateMode;
-use bitcoincore_rpc::jsonrpc::serde_json::json;
+use bitcoincore_rpc::jsonrpc;
+use bitcoincore_rpc::jsonrpc::simple_http::SimpleHttpTransport;
+use bitcoincore_rpc::Client;
 use bitcoincore_rpc::RpcApi;
-use bitcoincore_rpc::{Auth, Client};
 
 use folgore_common::client::fee_estimator::FeeEstimator;
-use folgore_common::client::fee_estimator::{FeePriority, FEE_RATES};
-use folgore_common::client::FolgoreBackend;
-use folgore_common::cln::plugin::types::LogLevel;
-use folgore_common::hex;
-use folgore_common::prelude::cln_plugin::error;
-use folgore_common::prelude::cln_plugin::errors;
-use folgore_common::prelude::cln_plugin::errors::PluginError;
-use folgore_common::prelude::cln_plugin::plugin;
-use folgore_common::prelude::json;
+use folgore_common::client::fee_estimator::{self, FeePriority, FeeTargets};
+use folgore_common::client::{
+    BackendConfig, BackendKind, BackendOption, BackendRegistry, BroadcastResult, ChainInfo,
+    FeeEstimates, FolgoreBackend, RawBlock, UtxoOut,
+};
+use folgore_common::errors::FolgoreError;
 use folgore_common::prelude::log;
-use folgore_common::utils::ByteBuf;
+use folgore_common::stragegy::RecoveryStrategy;
+use folgore_common::utils::{from_hex, ByteBuf};
+
+use crate::error::rpc_error;
+use crate::transport::HttpsTransport;
+
+/// Internals used by the fuzz targets, they are not
+/// part of the public API.
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing {
+    pub use crate::error::rpc_error;
+    pub use crate::transport::HttpsError;
+}
 
-pub struct BitcoinCore {
+/// Default timeout for a single RPC request, this
+/// is the same default used by core lightning `bcli`.
+pub const DEFAULT_RPC_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct BitcoinCore<R: RecoveryStrategy> {
     pub client: Client,
+    recovery_strategy: Arc<R>,
+    fee_targets: FeeTargets,
 }
 
-impl BitcoinCore {
-    pub fn new(url: &str, user: &str, pass: &str) -> Result<Self, errors::PluginError> {
-        let client = Client::new(url, Auth::UserPass(user.to_owned(), pass.to_owned()))
-            .map_err(|err| error!("{err}"))?;
-        Ok(Self { client })
+impl<R: RecoveryStrategy> BitcoinCore<R> {
+    /// Build a new bitcoin core client, the `url` can be
+    /// an `http://` or an `https://` URL. In the last case the
+    /// server certificate is verified before sending the request.
+    pub fn new(
+        url: &str,
+        user: &str,
+        pass: &str,
+        timeout: Option<Duration>,
+        strategy: Arc<R>,
+    ) -> Result<Self, FolgoreError> {
+        let timeout = timeout.unwrap_or(DEFAULT_RPC_TIMEOUT);
+        let client = if url.starts_with("https://") {
+            jsonrpc::Client::with_transport(HttpsTransport::new(url, user, pass, timeout))
+        } else if url.starts_with("http://") || !url.contains("://") {
+            let transport = SimpleHttpTransport::builder()
+                .url(url)
+                .map_err(|err| {
+                    FolgoreError::validation(&err)
+                        .with_backend(BackendKind::BitcoinCore)
+                        .with_source(err)
+                })?
+                .auth(user, Some(pass))
+                .timeout(timeout)
+                .build();
+            jsonrpc::Client::with_transport(transport)
+        } else {
+            return Err(FolgoreError::validation(format!(
+                "bitcoin url `{url}` with an unsupported scheme"
+            ))
+            .with_backend(BackendKind::BitcoinCore));
+        };
+        Ok(Self {
+            client: Client::from_jsonrpc(client),
+            recovery_strategy: strategy,
+            fee_targets: FeeTargets::default(),
+        })
+    }
+
+    /// Estimate the fees of the `targets` instead of the default ones.
+    pub fn with_fee_targets(mut self, targets: FeeTargets) -> Self {
+        self.fee_targets = targets;
+        self
     }
 }
 
-impl<T: Clone> FolgoreBackend<T> for BitcoinCore {
-    fn kind(&self) -> folgore_common::client::BackendKind {
-        folgore_common::client::BackendKind::BitcoinCore
+/// The options read by the bitcoin core backend.
+pub fn options() -> Vec<BackendOption> {
+    vec![
+        BackendOption::string("bitcoin-rpcpassword", "Bitcoin RPC password"),
+        BackendOption::string("bitcoin-rpcuser", "Bitcoin RPC use"),
+        BackendOption::string("bitcoin-rpcurl", "Set up the Bitcoin RPC URL"),
+        BackendOption::int(
+            "bitcoin-rpcclienttimeout",
+            "Timeout in seconds of a single bitcoin RPC request (by default 60)",
+        ),
+        FeeTargets::option(),
+    ]
+}
+
+impl<R: RecoveryStrategy> BitcoinCore<R> {
+    /// Build the bitcoin core backend from the options
+    /// returned by `options`.
+    pub fn from_config(config: &BackendConfig, strategy: Arc<R>) -> Result<Self, FolgoreError> {
+        let option = |name: &str, what: &str| {
+            config.get_str(name).ok_or(
+                FolgoreError::validation(format!("bitcoin {what} not specified"))
+                    .with_backend(BackendKind::BitcoinCore),
+            )
+        };
+        let timeout = config
+            .get_u64("bitcoin-rpcclienttimeout")
+            .map_err(|err| err.with_backend(BackendKind::BitcoinCore))?;
+        // a zero timeout disables the timeout of the transport
+        if timeout == Some(0) {
+            return Err(FolgoreError::validation(
+                "`bitcoin-rpcclienttimeout` must be greater than 0",
+            )
+            .with_backend(BackendKind::BitcoinCore));
+        }
+        let timeout = timeout.map(Duration::from_secs);
+        let fee_targets = FeeTargets::from_config(config)
+            .map_err(|err| err.with_backend(BackendKind::BitcoinCore))?;
+        Ok(Self::new(
+            &option("bitcoin-rpcurl", "url")?,
+            &option("bitcoin-rpcuser", "user")?,
+            &option("bitcoin-rpcpassword", "pass")?,
+            timeout,
+            strategy,
+        )?
+        .with_fee_targets(fee_targets))
     }
+}
 
-    fn sync_chain_info(
-        &self,
-        _: &mut plugin::Plugin<T>,
-        _: Option<u64>,
-    ) -> Result<json::Value, errors::PluginError> {
+/// Register the bitcoin core backend with the name `bitcoind`.
+pub fn register<R: RecoveryStrategy + 'static>(
+    registry: &mut BackendRegistry<R>,
+) -> Result<(), FolgoreError> {
+    registry.register(
+        "bitcoind",
+        options(),
+        Box::new(|config, strategy| Ok(Arc::new(BitcoinCore::from_config(config, strategy)?))),
+    )?;
+    Ok(())
+}
+
+/// Decode the raw transaction sent by core lightning.
+pub fn decode_transaction(raw_tx: &str) -> Result<Transaction, FolgoreError> {
+    let tx = from_hex(raw_tx).map_err(|err| err.with_backend(BackendKind::BitcoinCore))?;
+    deserialize(&tx).map_err(|err| {
+        FolgoreError::validation(format!("invalid transaction: {err}"))
+            .with_backend(BackendKind::BitcoinCore)
+    })
+}
+
+impl<R: RecoveryStrategy> FolgoreBackend for BitcoinCore<R> {
+    fn kind(&self) -> BackendKind {
+        BackendKind::BitcoinCore
+    }
+
+    fn sync_chain_info(&self, _: Option<u64>) -> Result<ChainInfo, FolgoreError> {
         let chaininfo = self
-            .client
-            .get_blockchain_info()
-            .map_err(|err| error!("{err}"))?;
-
-        Ok(json::json!({
-            "headercount": chaininfo.headers,
-            "blockcount": chaininfo.blocks,
-            "ibd": chaininfo.initial_block_download,
-            "chain": chaininfo.chain,
-        }))
+            .recovery_strategy
+            .apply(|| self.client.get_blockchain_info().map_err(rpc_error))?;
+
+        Ok(ChainInfo {
+            chain: chaininfo.chain.to_core_arg().to_owned(),
+            header_count: chaininfo.headers,
+            block_count: chaininfo.blocks,
+            ibd: chaininfo.initial_block_download,
+        })
     }
 
-    fn sync_block_by_height(
-        &self,
-        plugin: &mut plugin::Plugin<T>,
-        height: u64,
-    ) -> Result<json::Value, errors::PluginError> {
+    fn sync_block_by_height(&self, height: u64) -> Result<Option<RawBlock>, FolgoreError> {
         let current_height = self
-            .client
-            .get_block_count()
-            .map_err(|err| error!("{err}"))?;
+            .recovery_strategy
+            .apply(|| self.client.get_block_count().map_err(rpc_error))?;
         if current_height < height {
-            plugin.log(
-                LogLevel::Debug,
-                &format!("requesting block out of best chain. Block height wanted: {height}"),
-            );
-            return Ok(json!({
-                "blockhash": null,
-                "block": null,
-            }));
+            log::debug!("requesting block out of best chain. Block height wanted: {height}");
+            return Ok(None);
         }
         let block_header = self
-            .client
-            .get_block_hash(height)
-            .map_err(|err| error!("{err}"))?;
+            .recovery_strategy
+            .apply(|| self.client.get_block_hash(height).map_err(rpc_error))?;
         let block = self
-            .client
-            .get_block(&block_header)
-            .map_err(|err| error!("{err}"))?;
+            .recovery_strategy
+            .apply(|| self.client.get_block(&block_header).map_err(rpc_error))?;
 
         let serialize = serialize(&block);
-        let ser_str = serialize.as_slice();
-        Ok(json::json!({
-            "blockhash": block_header.to_string(),
-            "block": json::to_value(format!("{:20x}", ByteBuf(ser_str)))?,
+        Ok(Some(RawBlock {
+            hash: block_header.to_string(),
+            block: format!("{:02x}", ByteBuf(&serialize)),
         }))
     }
 
-    fn sync_estimate_fees(
-        &self,
-        _: &mut plugin::Plugin<T>,
-    ) -> Result<json::Value, errors::PluginError> {
+    fn sync_estimate_fees(&self) -> Result<FeeEstimates, FolgoreError> {
         #[derive(Serialize, Deserialize)]
         pub struct MinimumMempoolFee {
-            pub mempoolminfee: f32,
+            /// The minimum fee in BTC/kvB.
+            #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+            pub mempoolminfee: Amount,
         }
 
         let mut fee_map = BTreeMap::new();
         let fee: MinimumMempoolFee = self
-            .client
-            .call("getmempoolinfo", &[])
-            .map_err(|err| error!("{err}"))?;
-        let fee = fee.mempoolminfee;
-        fee_map.insert(0, (fee * 10000.0) as u64);
-        for FeePriority(block, target) in FEE_RATES.iter().cloned() {
+            .recovery_strategy
+            .apply(|| self.client.call("getmempoolinfo", &[]).map_err(rpc_error))?;
+        // core lightning wants the feerates in sat/kvB
+        fee_map.insert(0, fee.mempoolminfee.to_sat());
+        for FeePriority(block, mode) in self.fee_targets.iter().cloned() {
             let diff = block as u64;
-            let mode = match target {
-                "CONSERVATIVE" => EstimateMode::Conservative,
-                _ => {
-                    return Err(error!(
-                        "mode {target} unsupported by the plugin please report the bug"
-                    ))
-                }
-            };
-            let Ok(fees) = self.client.estimate_smart_fee(block, Some(mode)) else {
-                continue;
+            let mode = match mode {
+                fee_estimator::EstimateMode::Conservative => EstimateMode::Conservative,
+                fee_estimator::EstimateMode::Economical => EstimateMode::Economical,
             };
+            let fees = self.recovery_strategy.apply(|| {
+                self.client
+                    .estimate_smart_fee(block, Some(mode))
+                    .map_err(rpc_error)
+            })?;
+            // bitcoin core does not have enough data to estimate the target
             let Some(fee) = fees.fee_rate else {
                 continue;
             };
             fee_map.insert(diff, fee.to_sat());
         }
-        if fee_map.len() != FEE_RATES.len() {
-            return FeeEstimator::null_estimate_fees();
-        }
-        FeeEstimator::build_estimate_fees(&fee_map)
+        FeeEstimator::build_estimate_fees(&self.fee_targets, &fee_map)
     }
 
-    fn sync_get_utxo(
-        &self,
-        _: &mut plugin::Plugin<T>,
-        txid: &str,
-        idx: u64,
-    ) -> Result<json::Value, errors::PluginError> {
-        let utxo = self
-            .client
-            .get_tx_out(
-                &Txid::from_str(txid).map_err(|err| error!("{err}"))?,
-                idx as u32,
-                None,
-            )
-            .map_err(|err| error!("{err}"))?;
-        if utxo.is_none() {
-            return Ok(json::json!({
-                "script": null,
-                "amount": null,
-            }));
-        }
-        // SAFETY: this is safe to unwrap because we check the wrong case before
-        // so this will never fails.
-        #[allow(clippy::unwrap_used)]
-        let utxo = utxo.unwrap();
-        Ok(json::json!({
-            "script": String::from_utf8(utxo.script_pub_key.hex).map_err(|err| error!("{err}"))?,
-            "amount": utxo.value.to_sat(),
+    fn sync_get_utxo(&self, txid: &str, idx: u64) -> Result<Option<UtxoOut>, FolgoreError> {
+        let txid = Txid::from_str(txid).map_err(|err| {
+            FolgoreError::validation(format!("invalid txid `{txid}`: {err}"))
+                .with_backend(BackendKind::BitcoinCore)
+        })?;
+        let utxo = self.recovery_strategy.apply(|| {
+            self.client
+                .get_tx_out(&txid, idx as u32, None)
+                .map_err(rpc_error)
+        })?;
+        let Some(utxo) = utxo else {
+            return Ok(None);
+        };
+        Ok(Some(UtxoOut {
+            amount: utxo.value.to_sat(),
+            // the RPC client decode the script, so we encode it back
+            script: format!("{:02x}", ByteBuf(&utxo.script_pub_key.hex)),
         }))
     }
 
     fn sync_send_raw_transaction(
         &self,
-        _: &mut plugin::Plugin<T>,
         raw_tx: &str,
         _: bool,
-    ) -> Result<json::Value, errors::PluginError> {
-        use folgore_common::utils::bitcoin_hashes;
-        let hex_tx = hex!(raw_tx);
-        let tx: Transaction = deserialize(&hex_tx).map_err(|err| error!("{err}"))?;
+    ) -> Result<BroadcastResult, FolgoreError> {
+        let tx = decode_transaction(raw_tx)?;
         let result = self.client.send_raw_transaction(&tx);
         log::info!("{:?}", result);
-        Ok(json::json!({
-            "success": result.is_ok(),
-            "errmsg": result.err().map(|err| err.to_string()),
-        }))
+        match result {
+            Ok(_) => Ok(BroadcastResult::success()),
+            Err(err) => Ok(BroadcastResult::failure(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use folgore_common::conformance::{self, Fixture};
+    use folgore_common::errors::ErrorKind;
+    use folgore_common::prelude::json::{self, json, Value};
+    use folgore_common::stragegy::Transient;
+    use folgore_mock::bitcoind::BitcoindServer;
+    use folgore_mock::{MockBackend, NoRetry};
+
+    use super::*;
+
+    fn bitcoind(server: &BitcoindServer, pass: &str) -> BitcoinCore<NoRetry> {
+        let Ok(bitcoind) = BitcoinCore::new(
+            &server.url(),
+            "user",
+            pass,
+            Some(Duration::from_secs(5)),
+            Arc::new(NoRetry),
+        ) else {
+            panic!("impossible build the bitcoin core client");
+        };
+        bitcoind
+    }
+
+    fn server(mock: &Arc<MockBackend>) -> BitcoindServer {
+        let Ok(server) = BitcoindServer::start(mock.clone(), "user", "pass") else {
+            panic!("impossible start the bitcoin core server");
+        };
+        server
+    }
+
+    #[test]
+    fn test_conformance() {
+        let fixture = Fixture::regtest(10);
+        let mock = Arc::new(MockBackend::from_fixture(&fixture));
+        let server = server(&mock);
+        conformance::assert_conformance(&bitcoind(&server, "pass"), &fixture);
+    }
+
+    #[test]
+    fn test_rpc_backend() {
+        let mock = Arc::new(MockBackend::new(5));
+        let server = server(&mock);
+        let bitcoind = bitcoind(&server, "pass");
+
+        let Ok(info) = bitcoind.sync_chain_info(None) else {
+            panic!("chain info not returned");
+        };
+        assert_eq!(info.chain, "regtest");
+        assert_eq!(info.block_count, 4);
+        assert!(!info.ibd);
+
+        let Ok(Some(block)) = bitcoind.sync_block_by_height(4) else {
+            panic!("block 4 not found");
+        };
+        assert_eq!(mock.sync_block_by_height(4).ok().flatten(), Some(block));
+        // the block over the tip is not requested to the node
+        assert!(matches!(bitcoind.sync_block_by_height(5), Ok(None)));
+        assert_eq!(
+            server.requests()[server.requests().len() - 1],
+            "getblockcount"
+        );
+
+        // bitcoin core answers in BTC/kvB, core lightning wants sat/kvB
+        let Ok(fees) = bitcoind.sync_estimate_fees() else {
+            panic!("fees not returned");
+        };
+        let Ok(expected) = mock.sync_estimate_fees() else {
+            panic!("fees not returned");
+        };
+        assert_eq!(fees, expected);
+        assert_eq!(fees.floor, 1000);
+
+        let Ok(targets) = FeeTargets::parse("3,144:economical") else {
+            panic!("targets not parsed");
+        };
+        let Ok(fees) = self::bitcoind(&server, "pass")
+            .with_fee_targets(targets)
+            .sync_estimate_fees()
+        else {
+            panic!("fees not returned");
+        };
+        let estimate = |blocks: u64| {
+            expected
+                .feerates
+                .iter()
+                .find(|fee| fee.blocks >= blocks)
+                .map(|fee| fee.feerate)
+        };
+        assert_eq!(
+            fees.feerates
+                .iter()
+                .map(|fee| (fee.blocks, Some(fee.feerate)))
+                .collect::<Vec<_>>(),
+            vec![(3, estimate(3)), (144, estimate(100))]
+        );
+
+        let output = mock.create_output(10_000, "0014aa");
+        let Ok(Some(utxo)) = bitcoind.sync_get_utxo(&output.txid, 0) else {
+            panic!("utxo not found");
+        };
+        assert_eq!(utxo.amount, 10_000);
+        assert_eq!(utxo.script, "0014aa");
+        assert!(matches!(bitcoind.sync_get_utxo(&output.txid, 1), Ok(None)));
+        let Err(err) = bitcoind.sync_get_utxo("00", 0) else {
+            panic!("invalid txid accepted");
+        };
+        assert_eq!(err.kind(), ErrorKind::Validation);
+        for tx in ["zz", "0200"] {
+            let Err(err) = bitcoind.sync_send_raw_transaction(tx, false) else {
+                panic!("invalid transaction `{tx}` accepted");
+            };
+            assert_eq!(err.kind(), ErrorKind::Validation);
+        }
+    }
+
+    #[test]
+    fn test_from_config() {
+        let mock = Arc::new(MockBackend::new(5));
+        let server = server(&mock);
+        let config = |options: Value| BackendConfig {
+            network: "regtest".to_owned(),
+            options: json::from_value(options).unwrap_or_default(),
+            ..BackendConfig::default()
+        };
+        let options = json!({
+            "bitcoin-rpcurl": server.url(),
+            "bitcoin-rpcuser": "user",
+            "bitcoin-rpcpassword": "pass",
+            "bitcoin-rpcclienttimeout": 30,
+        });
+        let Ok(bitcoind) = BitcoinCore::from_config(&config(options.clone()), Arc::new(NoRetry))
+        else {
+            panic!("bitcoin core not built");
+        };
+        assert_eq!(
+            bitcoind
+                .sync_chain_info(None)
+                .ok()
+                .map(|info| info.block_count),
+            Some(4)
+        );
+
+        let kind = |options: Value| {
+            BitcoinCore::from_config(&config(options), Arc::new(NoRetry))
+                .err()
+                .map(|err| err.kind())
+        };
+        for (name, value) in [
+            ("bitcoin-rpcclienttimeout", json!(0)),
+            ("bitcoin-rpcclienttimeout", json!("ten")),
+            ("bitcoin-rpcurl", json!("ftp://127.0.0.1:8332")),
+            ("bitcoin-rpcurl", Value::Null),
+        ] {
+            let mut options = options.clone();
+            options[name] = value.clone();
+            assert_eq!(
+                kind(options),
+                Some(ErrorKind::Validation),
+                "`{name}` = `{value}` accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn test_rpc_errors() {
+        let mock = Arc::new(MockBackend::new(5));
+        let server = server(&mock);
+        let bitcoind = bitcoind(&server, "pass");
+
+        let kind = |result: Result<ChainInfo, FolgoreError>| result.err().map(|err| err.kind());
+        assert_eq!(
+            kind(self::bitcoind(&server, "wrong").sync_chain_info(None)),
+            Some(ErrorKind::Auth)
+        );
+        // the node is warming up
+        server.fail_rpc("getblockchaininfo", -28, 1);
+        let Err(err) = bitcoind.sync_chain_info(None) else {
+            panic!("the error is not returned");
+        };
+        assert_eq!(err.kind(), ErrorKind::Network);
+        assert!(err.is_transient());
+        assert_eq!(err.backend(), Some(BackendKind::BitcoinCore));
+        server.fail("getblockchaininfo", 503, 1);
+        assert_eq!(
+            kind(bitcoind.sync_chain_info(None)),
+            Some(ErrorKind::Network)
+        );
+        server.fail("getblockchaininfo", 200, 1);
+        assert_eq!(
+            kind(bitcoind.sync_chain_info(None)),
+            Some(ErrorKind::InvalidResponse)
+        );
+        assert!(bitcoind.sync_chain_info(None).is_ok());
+
+        server.fail_rpc("getblock", -5, 1);
+        let Err(err) = bitcoind.sync_block_by_height(1) else {
+            panic!("the error is not returned");
+        };
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+
+        // the error of a single estimate is not hidden
+        server.fail_rpc("estimatesmartfee", -32603, 1);
+        let Err(err) = bitcoind.sync_estimate_fees() else {
+            panic!("the error is not returned");
+        };
+        assert_eq!(err.backend(), Some(BackendKind::BitcoinCore));
+        server.fail("estimatesmartfee", 503, 1);
+        assert!(bitcoind
+            .sync_estimate_fees()
+            .is_err_and(|err| err.is_transient()));
+        // bitcoin core has not enough data to estimate the fees
+        mock.set_fees(FeeEstimates {
+            floor: 1000,
+            feerates: vec![],
+        });
+        let Ok(fees) = bitcoind.sync_estimate_fees() else {
+            panic!("fees not returned");
+        };
+        assert!(fees.feerates.is_empty());
+
+        drop(server);
+        assert_eq!(
+            kind(bitcoind.sync_chain_info(None)),
+            Some(ErrorKind::Network)
+        );
     }
 }
@@ -0,0 +1,157 @@
+//! HTTPS transport for the bitcoin core JSON RPC client.
+//!
+//! The `jsonrpc` crate used by `bitcoincore-rpc` ships only
+//! a plain HTTP transport, so this module implements the
+//! `Transport` trait on top of `ureq`, which verify the
+//! server certificate against the webpki root store.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::fmt;
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bitcoincore_rpc::jsonrpc::{Error, Request, Response, Transport};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use folgore_common::prelude::json;
+
+/// Error returned by the HTTPS transport.
+#[derive(Debug)]
+pub enum HttpsError {
+    /// The server answer with an HTTP error code and
+    /// a body that is not a JSON RPC response.
+    Http(u16),
+    /// The server answer with a success code, but the body
+    /// is not a JSON RPC response.
+    InvalidBody(u16),
+    /// The request fails before receiving a response
+    /// (e.g. TLS handshake, connection refused, timeout).
+    Request(Box<ureq::Error>),
+}
+
+impl fmt::Display for HttpsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http(code) => write!(f, "http error code {code}"),
+            Self::InvalidBody(code) => {
+                write!(f, "invalid JSON RPC response with http code {code}")
+            }
+            Self::Request(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for HttpsError {}
+
+impl From<HttpsError> for Error {
+    fn from(err: HttpsError) -> Self {
+        Error::Transport(Box::new(err))
+    }
+}
+
+/// JSON RPC transport that speak with bitcoin core
+/// over a TLS connection.
+pub struct HttpsTransport {
+    agent: ureq::Agent,
+    url: String,
+    auth: String,
+}
+
+impl HttpsTransport {
+    pub fn new(url: &str, user: &str, pass: &str, timeout: Duration) -> Self {
+        let agent = ureq::AgentBuilder::new().timeout(timeout).build();
+        let auth = format!("Basic {}", STANDARD.encode(format!("{user}:{pass}")));
+        Self {
+            agent,
+            url: url.to_owned(),
+            auth,
+        }
+    }
+
+    fn request<R: Serialize, T: DeserializeOwned>(&self, body: &R) -> Result<T, Error> {
+        let body = json::to_string(body)?;
+        let response = self
+            .agent
+            .post(&self.url)
+            .set("Authorization", &self.auth)
+            .set("Content-Type", "application/json")
+            .send_string(&body);
+        let (invalid, response) = match response {
+            Ok(response) => (HttpsError::InvalidBody(response.status()), response),
+            // bitcoin core return an HTTP error code also when
+            // the body contains a valid JSON RPC error, so we
+            // try to decode it anyway.
+            Err(ureq::Error::Status(code, response)) => (HttpsError::Http(code), response),
+            Err(err) => return Err(HttpsError::Request(Box::new(err)).into()),
+        };
+        json::from_reader(response.into_reader()).map_err(|_| invalid.into())
+    }
+}
+
+impl Transport for HttpsTransport {
+    fn send_request(&self, req: Request) -> Result<Response, Error> {
+        self.request(&req)
+    }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, Error> {
+        self.request(&reqs)
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bitcoincore_rpc::jsonrpc;
+    use bitcoincore_rpc::{Client, RpcApi};
+
+    use folgore_common::errors::ErrorKind;
+    use folgore_mock::bitcoind::BitcoindServer;
+    use folgore_mock::MockBackend;
+
+    use super::HttpsTransport;
+    use crate::error::rpc_error;
+
+    /// The transport does not depend on TLS, so it is
+    /// tested against the plain HTTP stand-in server.
+    fn client(server: &BitcoindServer, pass: &str) -> Client {
+        let transport = HttpsTransport::new(&server.url(), "user", pass, Duration::from_secs(5));
+        Client::from_jsonrpc(jsonrpc::Client::with_transport(transport))
+    }
+
+    #[test]
+    fn test_https_transport() {
+        let mock = Arc::new(MockBackend::new(5));
+        let Ok(server) = BitcoindServer::start(mock, "user", "pass") else {
+            panic!("impossible start the bitcoin core server");
+        };
+        let client = client(&server, "pass");
+        assert_eq!(client.get_block_count().ok(), Some(4));
+
+        let kind = |result: Result<u64, bitcoincore_rpc::Error>| {
+            result.err().map(|err| rpc_error(err).kind())
+        };
+        assert_eq!(
+            kind(self::client(&server, "wrong").get_block_count()),
+            Some(ErrorKind::Auth)
+        );
+        // the JSON RPC error is decoded also with an HTTP error code
+        server.fail_rpc("getblockcount", -5, 1);
+        assert_eq!(kind(client.get_block_count()), Some(ErrorKind::NotFound));
+        server.fail("getblockcount", 503, 1);
+        assert_eq!(kind(client.get_block_count()), Some(ErrorKind::Network));
+        // a success code with a body that is not JSON RPC
+        server.fail("getblockcount", 200, 1);
+        assert_eq!(
+            kind(client.get_block_count()),
+            Some(ErrorKind::InvalidResponse)
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//! Health check of a backend, the exit codes
+//! follow the convention of the monitoring tools
+//! (e.g: nagios).
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::fmt;
+use std::process::ExitCode;
+
+use folgore_common::client::{chain_name, ChainInfo, FolgoreBackend};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Health {
+    /// The backend is answering and it is synced.
+    Ok(ChainInfo),
+    /// The backend is answering but it is still syncing.
+    Warning(String),
+    /// The backend is failing or it is on the wrong chain.
+    Critical(String),
+    /// The backend can not be checked (e.g: invalid configuration).
+    Unknown,
+}
+
+impl Health {
+    pub fn check(client: &dyn FolgoreBackend, network: &str) -> Self {
+        match client.sync_chain_info(None) {
+            Ok(info) => Self::from_chain_info(info, network),
+            Err(err) => Self::Critical(format!("client `{}`: {err}", client.kind())),
+        }
+    }
+
+    fn from_chain_info(info: ChainInfo, network: &str) -> Self {
+        if let Some(expected) = chain_name(network) {
+            if info.chain != expected {
+                return Self::Critical(format!(
+                    "running on chain `{}` instead of `{expected}`",
+                    info.chain
+                ));
+            }
+        }
+        if info.ibd {
+            return Self::Warning(format!(
+                "initial block download at height {}",
+                info.block_count
+            ));
+        }
+        if info.header_count > info.block_count {
+            return Self::Warning(format!(
+                "syncing blocks {}/{}",
+                info.block_count, info.header_count
+            ));
+        }
+        Self::Ok(info)
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::Ok(_) => 0,
+            Self::Warning(_) => 1,
+            Self::Critical(_) => 2,
+            Self::Unknown => 3,
+        }
+    }
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ok(info) => write!(
+                f,
+                "OK: chain `{}` at height {}",
+                info.chain, info.block_count
+            ),
+            Self::Warning(msg) => write!(f, "WARNING: {msg}"),
+            Self::Critical(msg) => write!(f, "CRITICAL: {msg}"),
+            Self::Unknown => write!(f, "UNKNOWN"),
+        }
+    }
+}
+
+impl From<Health> for ExitCode {
+    fn from(health: Health) -> Self {
+        ExitCode::from(health.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(chain: &str, header_count: u64, block_count: u64, ibd: bool) -> ChainInfo {
+        ChainInfo {
+            chain: chain.to_owned(),
+            header_count,
+            block_count,
+            ibd,
+        }
+    }
+
+    #[test]
+    fn test_health_codes() {
+        let health = Health::from_chain_info(info("main", 10, 10, false), "bitcoin");
+        assert_eq!(health.code(), 0);
+        let health = Health::from_chain_info(info("main", 12, 10, false), "bitcoin");
+        assert_eq!(health.code(), 1);
+        let health = Health::from_chain_info(info("main", 10, 10, true), "bitcoin");
+        assert_eq!(health.code(), 1);
+        let health = Health::from_chain_info(info("test", 10, 10, false), "bitcoin");
+        assert_eq!(health.code(), 2);
+        assert_eq!(Health::Unknown.code(), 3);
+    }
+}
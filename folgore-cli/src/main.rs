@@ -0,0 +1,279 @@
+//! Command line tool to query a folgore backend
+//! outside core lightning.
+//!
+//! The backend is built with the same options of the
+//! plugin, and the result of each command is printed
+//! in the same format returned to core lightning.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+#![deny(clippy::unwrap_used)]
+mod health;
+
+use std::collections::HashMap;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use serde_json::{json, Value};
+
+use folgore_common::client::{
+    bcli, trace, BackendConfig, BackendKind, BackendRegistry, FolgoreBackend,
+};
+use folgore_common::errors::FolgoreError;
+use folgore_common::recovery::{RetryConfig, RetryKind, TimeoutRetry};
+
+use crate::health::Health;
+
+/// The backends compiled inside the command line tool.
+fn registry() -> Result<BackendRegistry<TimeoutRetry>, FolgoreError> {
+    #[allow(unused_mut)]
+    let mut registry = BackendRegistry::new();
+    #[cfg(feature = "nakamoto")]
+    folgore_nakamoto::register(&mut registry)?;
+    #[cfg(feature = "esplora")]
+    folgore_esplora::register(&mut registry)?;
+    #[cfg(feature = "bitcoind")]
+    folgore_bitcoind::register(&mut registry)?;
+    trace::register(&mut registry)?;
+    Ok(registry)
+}
+
+fn command(registry: &BackendRegistry<TimeoutRetry>) -> Command {
+    let mut command = Command::new("folgore-cli")
+        .about("Query a folgore backend outside core lightning")
+        .subcommand_required(true)
+        .arg(
+            Arg::new("bitcoin-client")
+                .long("bitcoin-client")
+                .global(true)
+                .default_value(registry.names().first().copied().unwrap_or("esplora"))
+                .help("The client to query"),
+        )
+        .arg(
+            Arg::new("network")
+                .long("network")
+                .global(true)
+                .default_value("bitcoin")
+                .help("The core lightning network name"),
+        )
+        .arg(
+            Arg::new("lightning-dir")
+                .long("lightning-dir")
+                .global(true)
+                .help("The core lightning directory (by default `~/.lightning`)"),
+        )
+        .subcommand(
+            Command::new("getchaininfo")
+                .about("Fetch the information about the chain")
+                .arg(
+                    Arg::new("last-height")
+                        .long("last-height")
+                        .value_parser(value_parser!(u64)),
+                ),
+        )
+        .subcommand(Command::new("estimatefees").about("Fetch the fee estimation"))
+        .subcommand(
+            Command::new("getrawblockbyheight")
+                .about("Fetch the raw block by height")
+                .arg(
+                    Arg::new("height")
+                        .required(true)
+                        .value_parser(value_parser!(u64)),
+                ),
+        )
+        .subcommand(
+            Command::new("getutxout")
+                .about("Fetch the utxo with {txid} and {vout}")
+                .arg(Arg::new("txid").required(true))
+                .arg(
+                    Arg::new("vout")
+                        .required(true)
+                        .value_parser(value_parser!(u64)),
+                ),
+        )
+        .subcommand(
+            Command::new("sendrawtransaction")
+                .about("Publish a new transaction")
+                .arg(Arg::new("tx").required(true))
+                .arg(
+                    Arg::new("allowhighfees")
+                        .long("allowhighfees")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(Command::new("health").about(
+            "Check the backend, exit with 0 if it is ok, 1 if it is syncing, 2 if it is failing and 3 on a configuration error",
+        ));
+    // the options of the backends are the same of the plugin
+    for option in registry.options() {
+        command = command.arg(
+            Arg::new(option.name)
+                .long(option.name)
+                .global(true)
+                .help(option.description),
+        );
+    }
+    command
+}
+
+fn new_client(
+    registry: &BackendRegistry<TimeoutRetry>,
+    matches: &ArgMatches,
+) -> Result<Arc<dyn FolgoreBackend + Send + Sync>, FolgoreError> {
+    let lightning_dir = matches
+        .get_one::<String>("lightning-dir")
+        .cloned()
+        .or_else(|| {
+            std::env::var("HOME")
+                .ok()
+                .map(|home| format!("{home}/.lightning"))
+        })
+        .unwrap_or(".lightning".to_owned());
+    let network = matches
+        .get_one::<String>("network")
+        .cloned()
+        .unwrap_or_default();
+    let mut options = HashMap::new();
+    for option in registry.options() {
+        if let Some(value) = matches.get_one::<String>(option.name) {
+            options.insert(option.name.to_owned(), Value::String(value.to_owned()));
+        }
+    }
+    let config = BackendConfig {
+        rpc_path: format!("{lightning_dir}/{network}/lightning-rpc"),
+        lightning_dir,
+        network,
+        options,
+    };
+    let client = matches
+        .get_one::<String>("bitcoin-client")
+        .cloned()
+        .unwrap_or_default();
+    if !registry.contains(&client) && BackendKind::try_from(client.as_str()).is_ok() {
+        return Err(FolgoreError::unsupported(format!(
+            "client `{client}` is not compiled in, please build the tool with the `{client}` feature"
+        )));
+    }
+    // the command line tool fails at the first error, so
+    // the answer is the one of the backend without retry
+    let strategy = TimeoutRetry::with_config(RetryConfig {
+        kind: RetryKind::None,
+        ..RetryConfig::default()
+    });
+    registry.create(&client, &config, Arc::new(strategy))
+}
+
+/// Run the command and return the JSON in the same
+/// format returned by the plugin.
+fn run(client: &dyn FolgoreBackend, name: &str, args: &ArgMatches) -> Result<Value, FolgoreError> {
+    let result = match name {
+        "getchaininfo" => {
+            let info = client.sync_chain_info(args.get_one::<u64>("last-height").copied())?;
+            bcli::chain_info(&info)
+        }
+        "estimatefees" => bcli::estimate_fees(&client.sync_estimate_fees()?),
+        "getrawblockbyheight" => {
+            let height = args.get_one::<u64>("height").copied().unwrap_or_default();
+            bcli::raw_block(client.sync_block_by_height(height)?.as_ref())
+        }
+        "getutxout" => {
+            let txid = args.get_one::<String>("txid").cloned().unwrap_or_default();
+            let vout = args.get_one::<u64>("vout").copied().unwrap_or_default();
+            bcli::utxo(client.sync_get_utxo(&txid, vout)?.as_ref())
+        }
+        "sendrawtransaction" => {
+            let tx = args.get_one::<String>("tx").cloned().unwrap_or_default();
+            let result = client.sync_send_raw_transaction(&tx, args.get_flag("allowhighfees"))?;
+            bcli::send_raw_transaction(&result)
+        }
+        _ => return Err(FolgoreError::unsupported(format!("command `{name}`"))),
+    };
+    Ok(result)
+}
+
+fn print_error(err: &FolgoreError) {
+    eprintln!(
+        "{}",
+        json!({
+            "code": err.kind().code(),
+            "kind": err.kind().to_string(),
+            "backend": err.backend().map(|backend| backend.to_string()),
+            "message": err.to_string(),
+        })
+    );
+}
+
+fn main() -> ExitCode {
+    env_logger::init();
+    let registry = match registry() {
+        Ok(registry) => registry,
+        Err(err) => {
+            print_error(&err);
+            return Health::Unknown.into();
+        }
+    };
+    let matches = command(&registry).get_matches();
+    let client = match new_client(&registry, &matches) {
+        Ok(client) => client,
+        Err(err) => {
+            print_error(&err);
+            return Health::Unknown.into();
+        }
+    };
+    let Some((name, args)) = matches.subcommand() else {
+        return Health::Unknown.into();
+    };
+    if name == "health" {
+        let network = matches
+            .get_one::<String>("network")
+            .cloned()
+            .unwrap_or_default();
+        let health = Health::check(client.as_ref(), &network);
+        println!("{health}");
+        return health.into();
+    }
+    match run(client.as_ref(), name, args) {
+        Ok(result) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&result).unwrap_or(result.to_string())
+            );
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            print_error(&err);
+            Health::Critical(err.to_string()).into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command() {
+        let Ok(registry) = registry() else {
+            panic!("impossible register the backends");
+        };
+        command(&registry).debug_assert();
+        let matches = command(&registry).try_get_matches_from([
+            "folgore-cli",
+            "getutxout",
+            "txid",
+            "1",
+            "--bitcoin-client",
+            "esplora",
+            "--bitcoin-esplora-url",
+            "http://localhost:3000",
+        ]);
+        let Ok(matches) = matches else {
+            panic!("invalid command line");
+        };
+        assert_eq!(
+            matches.get_one::<String>("bitcoin-client").cloned(),
+            Some("esplora".to_owned())
+        );
+        assert!(new_client(&registry, &matches).is_ok());
+    }
+}
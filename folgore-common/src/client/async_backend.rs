@@ -0,0 +1,316 @@
+//! Async interface of a folgore backend.
+//!
+//! The backends implemented inside folgore are using
+//! blocking clients, and none of them has a native async
+//! implementation yet, so the async interface runs the call
+//! on the tokio blocking thread pool, in this way an async
+//! service can issue concurrent requests without blocking
+//! the runtime. A backend built by the `BackendRegistry` is
+//! an `Arc<dyn FolgoreBackend + Send + Sync>`, so it can be
+//! used with this interface too.
+//!
+//! The module is available with the `async` feature.
+//!
+//! The `BlockingBackend` does the opposite, and allows to use
+//! an async backend where the sync interface is required
+//! (e.g: the core lightning plugin).
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use tokio::runtime::{Builder, Runtime};
+
+use crate::client::{
+    BackendKind, BroadcastResult, Capabilities, ChainInfo, FeeEstimates, FolgoreBackend, RawBlock,
+    UtxoOut,
+};
+use crate::errors::FolgoreError;
+
+/// Boxed future returned by the async backend, so the
+/// trait can be used as trait object.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Async version of the `FolgoreBackend`, see the
+/// sync trait for the documentation of each method.
+pub trait AsyncFolgoreBackend: Send + Sync {
+    fn kind(&self) -> BackendKind;
+
+    fn capabilities(&self) -> Capabilities;
+
+    fn chain_info(
+        &self,
+        known_height: Option<u64>,
+    ) -> BoxFuture<'_, Result<ChainInfo, FolgoreError>>;
+
+    fn estimate_fees(&self) -> BoxFuture<'_, Result<FeeEstimates, FolgoreError>>;
+
+    fn block_by_height(&self, height: u64)
+        -> BoxFuture<'_, Result<Option<RawBlock>, FolgoreError>>;
+
+    fn get_utxo(
+        &self,
+        txid: &str,
+        idx: u64,
+    ) -> BoxFuture<'_, Result<Option<UtxoOut>, FolgoreError>>;
+
+    fn send_raw_transaction(
+        &self,
+        tx: &str,
+        allow_high_fees: bool,
+    ) -> BoxFuture<'_, Result<BroadcastResult, FolgoreError>>;
+}
+
+/// Run the blocking call on the tokio blocking thread pool.
+async fn spawn_blocking<B, T, F>(backend: &Arc<B>, cb: F) -> Result<T, FolgoreError>
+where
+    B: FolgoreBackend + Send + Sync + ?Sized + 'static,
+    T: Send + 'static,
+    F: FnOnce(&B) -> Result<T, FolgoreError> + Send + 'static,
+{
+    let backend = backend.clone();
+    let kind = backend.kind();
+    tokio::task::spawn_blocking(move || cb(&backend))
+        .await
+        .map_err(|err| {
+            FolgoreError::network(format!("backend task fails: {err}"))
+                .with_backend(kind)
+                .with_source(err)
+        })?
+}
+
+/// Every sync backend (e.g: esplora, bitcoind and nakamoto)
+/// shared with an `Arc` is also an async backend.
+impl<B> AsyncFolgoreBackend for Arc<B>
+where
+    B: FolgoreBackend + Send + Sync + ?Sized + 'static,
+{
+    fn kind(&self) -> BackendKind {
+        self.as_ref().kind()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.as_ref().capabilities()
+    }
+
+    fn chain_info(
+        &self,
+        known_height: Option<u64>,
+    ) -> BoxFuture<'_, Result<ChainInfo, FolgoreError>> {
+        Box::pin(spawn_blocking(self, move |backend| {
+            backend.sync_chain_info(known_height)
+        }))
+    }
+
+    fn estimate_fees(&self) -> BoxFuture<'_, Result<FeeEstimates, FolgoreError>> {
+        Box::pin(spawn_blocking(self, |backend| backend.sync_estimate_fees()))
+    }
+
+    fn block_by_height(
+        &self,
+        height: u64,
+    ) -> BoxFuture<'_, Result<Option<RawBlock>, FolgoreError>> {
+        Box::pin(spawn_blocking(self, move |backend| {
+            backend.sync_block_by_height(height)
+        }))
+    }
+
+    fn get_utxo(
+        &self,
+        txid: &str,
+        idx: u64,
+    ) -> BoxFuture<'_, Result<Option<UtxoOut>, FolgoreError>> {
+        let txid = txid.to_owned();
+        Box::pin(spawn_blocking(self, move |backend| {
+            backend.sync_get_utxo(&txid, idx)
+        }))
+    }
+
+    fn send_raw_transaction(
+        &self,
+        tx: &str,
+        allow_high_fees: bool,
+    ) -> BoxFuture<'_, Result<BroadcastResult, FolgoreError>> {
+        let tx = tx.to_owned();
+        Box::pin(spawn_blocking(self, move |backend| {
+            backend.sync_send_raw_transaction(&tx, allow_high_fees)
+        }))
+    }
+}
+
+/// Adapter that expose an async backend with the sync
+/// interface, it owns a single thread runtime so it must
+/// not be used inside another tokio runtime.
+pub struct BlockingBackend<A: AsyncFolgoreBackend> {
+    backend: A,
+    runtime: Runtime,
+}
+
+impl<A: AsyncFolgoreBackend> BlockingBackend<A> {
+    pub fn new(backend: A) -> Result<Self, FolgoreError> {
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|err| {
+                FolgoreError::unsupported(format!("impossible build the tokio runtime: {err}"))
+                    .with_backend(backend.kind())
+                    .with_source(err)
+            })?;
+        Ok(Self { backend, runtime })
+    }
+}
+
+impl<A: AsyncFolgoreBackend> FolgoreBackend for BlockingBackend<A> {
+    fn kind(&self) -> BackendKind {
+        self.backend.kind()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.backend.capabilities()
+    }
+
+    fn sync_chain_info(&self, known_height: Option<u64>) -> Result<ChainInfo, FolgoreError> {
+        self.runtime.block_on(self.backend.chain_info(known_height))
+    }
+
+    fn sync_estimate_fees(&self) -> Result<FeeEstimates, FolgoreError> {
+        self.runtime.block_on(self.backend.estimate_fees())
+    }
+
+    fn sync_block_by_height(&self, height: u64) -> Result<Option<RawBlock>, FolgoreError> {
+        self.runtime.block_on(self.backend.block_by_height(height))
+    }
+
+    fn sync_get_utxo(&self, txid: &str, idx: u64) -> Result<Option<UtxoOut>, FolgoreError> {
+        self.runtime.block_on(self.backend.get_utxo(txid, idx))
+    }
+
+    fn sync_send_raw_transaction(
+        &self,
+        tx: &str,
+        allow_high_fees: bool,
+    ) -> Result<BroadcastResult, FolgoreError> {
+        self.runtime
+            .block_on(self.backend.send_raw_transaction(tx, allow_high_fees))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier};
+
+    use super::*;
+    use crate::client::{BackendConfig, BackendRegistry};
+
+    /// Sync backend that answers only when two requests
+    /// are running at the same time.
+    struct Concurrent {
+        barrier: Barrier,
+        calls: AtomicUsize,
+    }
+
+    impl FolgoreBackend for Concurrent {
+        fn kind(&self) -> BackendKind {
+            BackendKind::Esplora
+        }
+
+        fn sync_chain_info(&self, known_height: Option<u64>) -> Result<ChainInfo, FolgoreError> {
+            self.barrier.wait();
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let height = known_height.unwrap_or_default();
+            Ok(ChainInfo {
+                chain: "regtest".to_owned(),
+                header_count: height,
+                block_count: height,
+                ibd: false,
+            })
+        }
+
+        fn sync_estimate_fees(&self) -> Result<FeeEstimates, FolgoreError> {
+            self.barrier.wait();
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(FeeEstimates {
+                floor: 1000,
+                feerates: vec![],
+            })
+        }
+
+        fn sync_block_by_height(&self, _: u64) -> Result<Option<RawBlock>, FolgoreError> {
+            Ok(None)
+        }
+
+        fn sync_get_utxo(&self, _: &str, _: u64) -> Result<Option<UtxoOut>, FolgoreError> {
+            Err(FolgoreError::not_found("utxo not found"))
+        }
+
+        fn sync_send_raw_transaction(
+            &self,
+            _: &str,
+            _: bool,
+        ) -> Result<BroadcastResult, FolgoreError> {
+            Ok(BroadcastResult::success())
+        }
+    }
+
+    fn backend() -> Arc<Concurrent> {
+        Arc::new(Concurrent {
+            barrier: Barrier::new(2),
+            calls: AtomicUsize::new(0),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests() {
+        let backend = backend();
+        // the two calls wait each other, so this hangs if
+        // the requests are not running concurrently.
+        let (info, fees) = tokio::join!(backend.chain_info(Some(10)), backend.estimate_fees());
+        assert_eq!(info.ok().map(|info| info.block_count), Some(10));
+        assert_eq!(fees.ok().map(|fees| fees.floor), Some(1000));
+        assert_eq!(backend.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_registry_backend() {
+        let mut registry = BackendRegistry::<()>::new();
+        let registered = registry.register(
+            "concurrent",
+            vec![],
+            Box::new(|_, _| {
+                Ok(Arc::new(Concurrent {
+                    barrier: Barrier::new(1),
+                    calls: AtomicUsize::new(0),
+                }))
+            }),
+        );
+        assert!(registered.is_ok());
+        let Ok(backend) = registry.create("concurrent", &BackendConfig::default(), Arc::new(()))
+        else {
+            panic!("backend not created");
+        };
+        let info = backend.chain_info(Some(7)).await;
+        assert_eq!(info.ok().map(|info| info.block_count), Some(7));
+    }
+
+    #[test]
+    fn test_blocking_adapter() {
+        let backend: Arc<dyn FolgoreBackend + Send + Sync> = Arc::new(Concurrent {
+            barrier: Barrier::new(1),
+            calls: AtomicUsize::new(0),
+        });
+        let Ok(blocking) = BlockingBackend::new(backend) else {
+            panic!("impossible build the blocking backend");
+        };
+        assert_eq!(blocking.kind(), BackendKind::Esplora);
+        let info = blocking.sync_chain_info(Some(42));
+        assert_eq!(info.ok().map(|info| info.header_count), Some(42));
+        assert_eq!(blocking.sync_block_by_height(1).ok(), Some(None));
+        let err = blocking.sync_get_utxo("txid", 0).err();
+        assert_eq!(
+            err.map(|err| err.kind()),
+            Some(crate::errors::ErrorKind::NotFound)
+        );
+    }
+}
@@ -0,0 +1,91 @@
+//! Serialize the response of a backend in the JSON
+//! format returned by `bcli` to core lightning.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use serde_json::{json, Value};
+
+use crate::client::{BroadcastResult, ChainInfo, FeeEstimates, RawBlock, UtxoOut};
+
+/// The response of `getchaininfo`.
+pub fn chain_info(info: &ChainInfo) -> Value {
+    json!({
+        "chain": info.chain,
+        "headercount": info.header_count,
+        "blockcount": info.block_count,
+        "ibd": info.ibd,
+    })
+}
+
+/// The response of `estimatefees`, `feerates` is an
+/// empty array if the backend is not able to estimate
+/// the fees.
+pub fn estimate_fees(fees: &FeeEstimates) -> Value {
+    let feerates = fees
+        .feerates
+        .iter()
+        .map(|fee| json!({ "blocks": fee.blocks, "feerate": fee.feerate }))
+        .collect::<Vec<_>>();
+    json!({
+        "feerate_floor": fees.floor,
+        "feerates": feerates,
+    })
+}
+
+/// The response of `getrawblockbyheight`, both fields
+/// are null if the block is not found.
+pub fn raw_block(block: Option<&RawBlock>) -> Value {
+    json!({
+        "blockhash": block.map(|block| block.hash.clone()),
+        "block": block.map(|block| block.block.clone()),
+    })
+}
+
+/// The response of `getutxout`, both fields are
+/// null if the output is spent.
+pub fn utxo(utxo: Option<&UtxoOut>) -> Value {
+    json!({
+        "amount": utxo.map(|utxo| utxo.amount),
+        "script": utxo.map(|utxo| utxo.script.clone()),
+    })
+}
+
+/// The response of `sendrawtransaction`, `bcli` always
+/// returns the `errmsg` that is empty on success.
+pub fn send_raw_transaction(result: &BroadcastResult) -> Value {
+    json!({
+        "success": result.success,
+        "errmsg": result.errmsg.clone().unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::client::BlockFeeRate;
+
+    #[test]
+    fn test_bcli_responses() {
+        let fees = FeeEstimates {
+            floor: 1000,
+            feerates: vec![BlockFeeRate {
+                blocks: 2,
+                feerate: 45000,
+            }],
+        };
+        assert_eq!(
+            estimate_fees(&fees),
+            json!({
+                "feerate_floor": 1000,
+                "feerates": [{ "blocks": 2, "feerate": 45000 }],
+            })
+        );
+        assert_eq!(raw_block(None), json!({ "blockhash": null, "block": null }));
+        assert_eq!(utxo(None), json!({ "amount": null, "script": null }));
+        assert_eq!(
+            send_raw_transaction(&BroadcastResult::success()),
+            json!({ "success": true, "errmsg": "" })
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! Operations that a backend is able to serve.
+//!
+//! Not all the backends are able to serve all the `bcli`
+//! operations by themselves, e.g: nakamoto asks the fees
+//! to esplora, so each backend describes what it is
+//! serving natively, what it delegates to another backend
+//! and what it is not able to serve at all.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::fmt;
+
+use crate::client::BackendKind;
+use crate::errors::FolgoreError;
+
+/// An operation of the `FolgoreBackend`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Operation {
+    ChainInfo,
+    EstimateFees,
+    BlockByHeight,
+    GetUtxo,
+    SendRawTransaction,
+    DevUpdateUtxo,
+}
+
+impl Operation {
+    pub const ALL: [Operation; 6] = [
+        Self::ChainInfo,
+        Self::EstimateFees,
+        Self::BlockByHeight,
+        Self::GetUtxo,
+        Self::SendRawTransaction,
+        Self::DevUpdateUtxo,
+    ];
+
+    fn index(&self) -> usize {
+        match self {
+            Self::ChainInfo => 0,
+            Self::EstimateFees => 1,
+            Self::BlockByHeight => 2,
+            Self::GetUtxo => 3,
+            Self::SendRawTransaction => 4,
+            Self::DevUpdateUtxo => 5,
+        }
+    }
+}
+
+/// The name of the RPC method served by the operation.
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ChainInfo => write!(f, "getchaininfo"),
+            Self::EstimateFees => write!(f, "estimatefees"),
+            Self::BlockByHeight => write!(f, "getrawblockbyheight"),
+            Self::GetUtxo => write!(f, "getutxout"),
+            Self::SendRawTransaction => write!(f, "sendrawtransaction"),
+            Self::DevUpdateUtxo => write!(f, "dev-batch-utxoupdate"),
+        }
+    }
+}
+
+impl TryFrom<&str> for Operation {
+    type Error = FolgoreError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|op| op.to_string() == value)
+            .ok_or(FolgoreError::validation(format!(
+                "operation `{value}` not supported"
+            )))
+    }
+}
+
+/// How a backend serves an operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Support {
+    /// Served by the backend itself.
+    Native,
+    /// Forwarded to another backend.
+    Delegated(BackendKind),
+    /// The backend always fails with an unsupported error.
+    Unsupported,
+}
+
+impl Support {
+    pub fn is_supported(&self) -> bool {
+        !matches!(self, Self::Unsupported)
+    }
+}
+
+impl fmt::Display for Support {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Native => write!(f, "native"),
+            Self::Delegated(kind) => write!(f, "delegated to {kind}"),
+            Self::Unsupported => write!(f, "unsupported"),
+        }
+    }
+}
+
+/// The support of each operation of a backend.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    support: [Support; Operation::ALL.len()],
+}
+
+impl Capabilities {
+    /// All the operations are served natively.
+    pub fn native() -> Self {
+        Self {
+            support: [Support::Native; Operation::ALL.len()],
+        }
+    }
+
+    pub fn with(mut self, op: Operation, support: Support) -> Self {
+        self.support[op.index()] = support;
+        self
+    }
+
+    pub fn get(&self, op: Operation) -> Support {
+        self.support[op.index()]
+    }
+
+    pub fn supports(&self, op: Operation) -> bool {
+        self.get(op).is_supported()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Operation, Support)> + '_ {
+        Operation::ALL.iter().map(|op| (*op, self.get(*op)))
+    }
+}
+
+/// The dev command is not implemented by default.
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::native().with(Operation::DevUpdateUtxo, Support::Unsupported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities() {
+        let capabilities = Capabilities::default().with(
+            Operation::EstimateFees,
+            Support::Delegated(BackendKind::Esplora),
+        );
+        assert_eq!(capabilities.get(Operation::ChainInfo), Support::Native);
+        assert_eq!(
+            capabilities.get(Operation::EstimateFees),
+            Support::Delegated(BackendKind::Esplora)
+        );
+        assert!(capabilities.supports(Operation::EstimateFees));
+        assert!(!capabilities.supports(Operation::DevUpdateUtxo));
+        assert_eq!(capabilities.iter().count(), Operation::ALL.len());
+        assert_eq!(
+            format!("{}", Support::Delegated(BackendKind::Esplora)),
+            "delegated to esplora"
+        );
+    }
+}
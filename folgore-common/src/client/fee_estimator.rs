@@ -1,67 +1,417 @@
 //! Generic Fee estimator for all the folgore backend.
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
 
-use crate::prelude::cln_plugin::error;
-use crate::prelude::cln_plugin::errors::PluginError;
-use crate::prelude::json::Value;
+use crate::client::model::{BlockFeeRate, FeeEstimates};
+use crate::client::registry::{BackendConfig, BackendOption};
+use crate::errors::FolgoreError;
 
 /// Transaction fee rate in satoshis/vByte.
 pub type FeeRate = u64;
 
-#[derive(Clone)]
-pub struct FeePriority(pub u16, pub &'static str);
+/// The option with the block targets of the fee estimation.
+pub const FEE_TARGETS_OPTION: &str = "bitcoin-fee-targets";
+
+/// How much the estimation of a target is conservative, this
+/// is the `estimate_mode` of bitcoin core `estimatesmartfee`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EstimateMode {
+    Conservative,
+    Economical,
+}
+
+impl fmt::Display for EstimateMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Conservative => write!(f, "CONSERVATIVE"),
+            Self::Economical => write!(f, "ECONOMICAL"),
+        }
+    }
+}
+
+impl TryFrom<&str> for EstimateMode {
+    type Error = FolgoreError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.trim().to_lowercase().as_str() {
+            "conservative" => Ok(Self::Conservative),
+            "economical" => Ok(Self::Economical),
+            _ => Err(FolgoreError::validation(format!(
+                "fee estimate mode `{value}` not supported, use `conservative` or `economical`"
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeePriority(pub u16, pub EstimateMode);
 
 /// Various Fee combination that core lightning is using
 pub static FEE_RATES: [FeePriority; 4] = [
-    FeePriority(2, "CONSERVATIVE"),
-    FeePriority(6, "CONSERVATIVE"),
-    FeePriority(12, "CONSERVATIVE"),
-    FeePriority(100, "CONSERVATIVE"),
+    FeePriority(2, EstimateMode::Conservative),
+    FeePriority(6, EstimateMode::Conservative),
+    FeePriority(12, EstimateMode::Conservative),
+    FeePriority(100, EstimateMode::Conservative),
 ];
 
+/// The block targets estimated by the backends, ordered
+/// by blocks, by default the ones in `FEE_RATES`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeeTargets {
+    targets: Vec<FeePriority>,
+}
+
+impl Default for FeeTargets {
+    fn default() -> Self {
+        Self {
+            targets: FEE_RATES.to_vec(),
+        }
+    }
+}
+
+impl FeeTargets {
+    /// The maximum target estimated by bitcoin core.
+    pub const MAX_BLOCKS: u16 = 1008;
+
+    /// Build the targets ordered by blocks, a target must be
+    /// in the range estimated by bitcoin core and specified once.
+    pub fn new(mut targets: Vec<FeePriority>) -> Result<Self, FolgoreError> {
+        if targets.is_empty() {
+            return Err(FolgoreError::validation("no fee targets specified"));
+        }
+        targets.sort_by_key(|FeePriority(blocks, _)| *blocks);
+        for (idx, FeePriority(blocks, _)) in targets.iter().enumerate() {
+            if *blocks == 0 || *blocks > Self::MAX_BLOCKS {
+                return Err(FolgoreError::validation(format!(
+                    "fee target of {blocks} blocks out of range (1-{})",
+                    Self::MAX_BLOCKS
+                )));
+            }
+            if idx > 0 && targets[idx - 1].0 == *blocks {
+                return Err(FolgoreError::validation(format!(
+                    "fee target of {blocks} blocks specified twice"
+                )));
+            }
+        }
+        Ok(Self { targets })
+    }
+
+    /// Parse a comma separated list of targets, each target is a
+    /// number of blocks followed by an optional mode (by default
+    /// conservative), e.g: `2:conservative,6,12:economical,100`.
+    pub fn parse(value: &str) -> Result<Self, FolgoreError> {
+        let targets = value
+            .split(',')
+            .map(|target| {
+                let (blocks, mode) = match target.split_once(':') {
+                    Some((blocks, mode)) => (blocks, EstimateMode::try_from(mode)?),
+                    None => (target, EstimateMode::Conservative),
+                };
+                let blocks = blocks.trim().parse().map_err(|_| {
+                    FolgoreError::validation(format!("invalid fee target `{}`", target.trim()))
+                })?;
+                Ok(FeePriority(blocks, mode))
+            })
+            .collect::<Result<Vec<_>, FolgoreError>>()?;
+        Self::new(targets)
+    }
+
+    /// Read the targets from the `bitcoin-fee-targets` option.
+    pub fn from_config(config: &BackendConfig) -> Result<Self, FolgoreError> {
+        match config.get_str(FEE_TARGETS_OPTION) {
+            Some(value) => Self::parse(&value),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// The option read by `from_config`, the backends that
+    /// estimate the fees include it in their options.
+    pub fn option() -> BackendOption {
+        BackendOption::string(
+            FEE_TARGETS_OPTION,
+            "Comma separated list of the block targets of the fee estimation, each one with an optional `conservative` or `economical` mode (by default `2,6,12,100` conservative), e.g. `2:conservative,6,12:economical,100`",
+        )
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &FeePriority> + '_ {
+        self.targets.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.targets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+}
+
 pub struct FeeEstimator;
 
 impl FeeEstimator {
-    pub fn urgent_fee(fees: &BTreeMap<u64, FeeRate>) -> Option<FeeRate> {
-        fees.get(&6).copied()
+    /// Build the estimates of the `targets` from the fee rates
+    /// by blocks, where the key `0` is the minimum fee rate. The
+    /// estimates are null if one of them is missing.
+    pub fn build_estimate_fees(
+        targets: &FeeTargets,
+        fees: &BTreeMap<u64, FeeRate>,
+    ) -> Result<FeeEstimates, FolgoreError> {
+        let Some(floor) = fees.get(&0) else {
+            return Self::null_estimate_fees();
+        };
+        let mut feerates = vec![];
+        for FeePriority(blocks, _) in targets.iter() {
+            let Some(feerate) = fees.get(&(*blocks as u64)) else {
+                return Self::null_estimate_fees();
+            };
+            feerates.push(BlockFeeRate {
+                blocks: *blocks as u64,
+                feerate: *feerate,
+            });
+        }
+        Ok(FeeEstimates {
+            floor: *floor,
+            feerates,
+        })
     }
 
-    pub fn hightest_fee(fees: &BTreeMap<u64, FeeRate>) -> Option<FeeRate> {
-        fees.get(&2).copied()
+    pub fn null_estimate_fees() -> Result<FeeEstimates, FolgoreError> {
+        Ok(FeeEstimates {
+            floor: 1000,
+            feerates: vec![],
+        })
     }
+}
+
+/// How the fee rates of the last polls are combined.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmoothingKind {
+    /// Exponentially weighted moving average, with the
+    /// weight of a `window` periods average.
+    Ewma,
+    /// Median of the last `window` polls.
+    Median,
+}
+
+impl TryFrom<&str> for SmoothingKind {
+    type Error = FolgoreError;
 
-    pub fn normal_fee(fees: &BTreeMap<u64, FeeRate>) -> Option<FeeRate> {
-        fees.get(&12).copied()
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.trim() {
+            "ewma" => Ok(Self::Ewma),
+            "median" => Ok(Self::Median),
+            _ => Err(FolgoreError::validation(format!(
+                "fee smoothing `{value}` not supported, use `none`, `ewma` or `median`"
+            ))),
+        }
+    }
+}
+
+/// Smooth the fee rates returned by consecutive polls of
+/// `estimatefees`, so a backend that swings between two polls
+/// (e.g: esplora) does not make core lightning update the
+/// feerates of the channels at every poll.
+///
+/// Each target (and the floor) is smoothed on its own, and the
+/// result is limited to `max_change` percent of the previous
+/// value. The null estimates are returned as they are, and they
+/// are not counted in the window.
+#[derive(Clone, Debug)]
+pub struct FeeSmoother {
+    kind: SmoothingKind,
+    window: usize,
+    /// The maximum change of a fee rate between two polls, in percent.
+    max_change: Option<u64>,
+    /// The last fee rates returned by the backend, by blocks.
+    history: BTreeMap<u64, VecDeque<FeeRate>>,
+    /// The last fee rates returned to core lightning, by blocks.
+    last: BTreeMap<u64, FeeRate>,
+}
+
+impl FeeSmoother {
+    pub fn new(
+        kind: SmoothingKind,
+        window: usize,
+        max_change: Option<u64>,
+    ) -> Result<Self, FolgoreError> {
+        if window == 0 {
+            return Err(FolgoreError::validation(
+                "the fee smoothing window must be at least 1",
+            ));
+        }
+        Ok(Self {
+            kind,
+            window,
+            max_change,
+            history: BTreeMap::new(),
+            last: BTreeMap::new(),
+        })
     }
 
-    pub fn slow_fee(fees: &BTreeMap<u64, FeeRate>) -> Option<FeeRate> {
-        fees.get(&100).copied()
+    pub fn smooth(&mut self, fees: FeeEstimates) -> FeeEstimates {
+        if fees.feerates.is_empty() {
+            return fees;
+        }
+        FeeEstimates {
+            floor: self.smooth_rate(0, fees.floor),
+            feerates: fees
+                .feerates
+                .iter()
+                .map(|fee| BlockFeeRate {
+                    blocks: fee.blocks,
+                    feerate: self.smooth_rate(fee.blocks, fee.feerate),
+                })
+                .collect(),
+        }
     }
 
-    pub fn build_estimate_fees(fees: &BTreeMap<u64, FeeRate>) -> Result<Value, PluginError> {
-        let mut resp = vec![];
-        for (height, rate) in fees.iter() {
-            if *height == 0 {
-                continue;
+    fn smooth_rate(&mut self, blocks: u64, feerate: FeeRate) -> FeeRate {
+        let history = self.history.entry(blocks).or_default();
+        history.push_back(feerate);
+        while history.len() > self.window {
+            history.pop_front();
+        }
+        let prev = self.last.get(&blocks).copied();
+        let mut smoothed = match (self.kind, prev) {
+            (SmoothingKind::Median, _) => {
+                let mut rates = history.iter().copied().collect::<Vec<_>>();
+                rates.sort_unstable();
+                let mid = rates.len() / 2;
+                if rates.len() % 2 == 0 {
+                    // the average of the two in the middle, without overflow
+                    rates[mid - 1] / 2 + rates[mid] / 2 + (rates[mid - 1] % 2 + rates[mid] % 2) / 2
+                } else {
+                    rates[mid]
+                }
+            }
+            (SmoothingKind::Ewma, None) => feerate,
+            (SmoothingKind::Ewma, Some(prev)) => {
+                let alpha = 2.0 / (self.window as f64 + 1.0);
+                (alpha * feerate as f64 + (1.0 - alpha) * prev as f64).round() as FeeRate
             }
-            resp.push(serde_json::json!({
-                "blocks": height,
-                "feerate": rate,
-            }))
+        };
+        if let (Some(prev), Some(max_change)) = (prev, self.max_change) {
+            let step = (prev.saturating_mul(max_change) / 100).max(1);
+            smoothed = smoothed.clamp(prev.saturating_sub(step), prev.saturating_add(step));
+        }
+        self.last.insert(blocks, smoothed);
+        smoothed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_fee_targets() {
+        let Ok(targets) = FeeTargets::parse("12:economical, 2:Conservative,100,6") else {
+            panic!("targets not parsed");
+        };
+        assert_eq!(
+            targets.iter().cloned().collect::<Vec<_>>(),
+            vec![
+                FeePriority(2, EstimateMode::Conservative),
+                FeePriority(6, EstimateMode::Conservative),
+                FeePriority(12, EstimateMode::Economical),
+                FeePriority(100, EstimateMode::Conservative),
+            ]
+        );
+        for value in ["", "2,2", "0", "2000", "2:fast", "two"] {
+            assert!(FeeTargets::parse(value).is_err(), "`{value}` accepted");
         }
-        let floor = *fees
-            .get(&0)
-            .ok_or(error!("impossible get the minimum feerate"))? as i64;
-        Ok(serde_json::json!({
-            "feerate_floor": floor,
-            "feerates": resp,
-        }))
-    }
-
-    pub fn null_estimate_fees() -> Result<Value, PluginError> {
-        Ok(serde_json::json!({
-            "feerate_floor": 1000,
-            "feerates": {},
-        }))
+
+        let mut config = BackendConfig::default();
+        assert_eq!(
+            FeeTargets::from_config(&config).ok(),
+            Some(FeeTargets::default())
+        );
+        config
+            .options
+            .insert(FEE_TARGETS_OPTION.to_owned(), json!("3,144"));
+        let Ok(targets) = FeeTargets::from_config(&config) else {
+            panic!("targets not read");
+        };
+        assert_eq!(targets.len(), 2);
+
+        let fees = BTreeMap::from([(0, 1000), (3, 5000), (144, 2000), (6, 3000)]);
+        let Ok(estimates) = FeeEstimator::build_estimate_fees(&targets, &fees) else {
+            panic!("estimates not built");
+        };
+        assert_eq!(estimates.floor, 1000);
+        assert_eq!(
+            estimates.feerates,
+            vec![
+                BlockFeeRate {
+                    blocks: 3,
+                    feerate: 5000
+                },
+                BlockFeeRate {
+                    blocks: 144,
+                    feerate: 2000
+                },
+            ]
+        );
+        let fees = BTreeMap::from([(0, 1000), (3, 5000)]);
+        let Ok(estimates) = FeeEstimator::build_estimate_fees(&targets, &fees) else {
+            panic!("estimates not built");
+        };
+        assert!(estimates.feerates.is_empty());
+    }
+
+    #[test]
+    fn test_fee_smoother() {
+        let fees = |floor: FeeRate, rates: &[(u64, FeeRate)]| FeeEstimates {
+            floor,
+            feerates: rates
+                .iter()
+                .map(|(blocks, feerate)| BlockFeeRate {
+                    blocks: *blocks,
+                    feerate: *feerate,
+                })
+                .collect(),
+        };
+        let rates = |fees: &FeeEstimates| {
+            fees.feerates
+                .iter()
+                .map(|fee| fee.feerate)
+                .collect::<Vec<_>>()
+        };
+
+        let Ok(mut median) = FeeSmoother::new(SmoothingKind::Median, 3, None) else {
+            panic!("smoother not built");
+        };
+        for (rate, expected) in [(10_000, 10_000), (50_000, 30_000), (12_000, 12_000)] {
+            let smoothed = median.smooth(fees(1000, &[(2, rate)]));
+            assert_eq!(rates(&smoothed), vec![expected]);
+        }
+        // the spike is ignored while it is in the window
+        let smoothed = median.smooth(fees(1000, &[(2, 11_000)]));
+        assert_eq!(rates(&smoothed), vec![12_000]);
+        // the null estimates are not smoothed
+        let smoothed = median.smooth(fees(1000, &[]));
+        assert!(smoothed.feerates.is_empty());
+        let smoothed = median.smooth(fees(1000, &[(2, 10_000)]));
+        assert_eq!(rates(&smoothed), vec![11_000]);
+
+        // alpha is 0.5 with a window of 3
+        let Ok(mut ewma) = FeeSmoother::new(SmoothingKind::Ewma, 3, Some(10)) else {
+            panic!("smoother not built");
+        };
+        let smoothed = ewma.smooth(fees(1000, &[(2, 10_000), (6, 5000)]));
+        assert_eq!(rates(&smoothed), vec![10_000, 5000]);
+        let smoothed = ewma.smooth(fees(1100, &[(2, 30_000), (6, 5400)]));
+        assert_eq!(smoothed.floor, 1050);
+        // the average of 20_000 is limited to a change of 10%
+        assert_eq!(rates(&smoothed), vec![11_000, 5200]);
+
+        assert!(FeeSmoother::new(SmoothingKind::Ewma, 0, None).is_err());
+        assert_eq!(
+            SmoothingKind::try_from("median").ok(),
+            Some(SmoothingKind::Median)
+        );
+        assert!(SmoothingKind::try_from("mean").is_err());
     }
 }
@@ -1,28 +1,46 @@
 //! Future client interface definition.
+#[cfg(feature = "async")]
+pub mod async_backend;
+pub mod bcli;
+pub mod capability;
 pub mod fee_estimator;
+pub mod model;
+pub mod registry;
+pub mod trace;
 
 use std::fmt;
 
 use serde_json::Value;
 
-use clightningrpc_plugin::error;
-use clightningrpc_plugin::{errors::PluginError, plugin::Plugin};
+use crate::errors::FolgoreError;
 
+#[cfg(feature = "async")]
+pub use async_backend::{AsyncFolgoreBackend, BlockingBackend, BoxFuture};
+pub use capability::{Capabilities, Operation, Support};
+pub use model::{BlockFeeRate, BroadcastResult, ChainInfo, FeeEstimates, RawBlock, UtxoOut};
+pub use registry::{BackendConfig, BackendFactory, BackendOption, BackendRegistry};
+pub use trace::{RecordBackend, ReplayBackend};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum BackendKind {
     Nakamoto,
     Esplora,
     BitcoinCore,
+    /// A backend registered outside folgore, with its name.
+    Custom(&'static str),
 }
 
 impl TryFrom<&str> for BackendKind {
-    type Error = PluginError;
+    type Error = FolgoreError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         match value {
             "nakamoto" => Ok(Self::Nakamoto),
             "esplora" => Ok(Self::Esplora),
             "bitcoind" => Ok(Self::BitcoinCore),
-            _ => Err(error!("client {value} not supported")),
+            _ => Err(FolgoreError::unsupported(format!(
+                "client {value} not supported"
+            ))),
         }
     }
 }
@@ -33,72 +51,91 @@ impl fmt::Display for BackendKind {
             Self::Nakamoto => write!(f, "nakamoto"),
             Self::Esplora => write!(f, "esplora"),
             Self::BitcoinCore => write!(f, "bitcoind"),
+            Self::Custom(name) => write!(f, "{name}"),
         }
     }
 }
 
+/// Map the core lightning network name to the
+/// chain name (bip70) returned by `getchaininfo`.
+pub fn chain_name(network: &str) -> Option<&'static str> {
+    match network {
+        "bitcoin" => Some("main"),
+        "testnet" => Some("test"),
+        "testnet4" => Some("testnet4"),
+        "signet" => Some("signet"),
+        "regtest" => Some("regtest"),
+        "liquid" => Some("liquidv1"),
+        _ => None,
+    }
+}
+
 /// Future backend trait that implement an optional async and sync
 /// interface to work with a cln node that want access to a bitcoin
 /// blockchain.
-pub trait FolgoreBackend<T: Clone> {
+///
+/// The trait does not depend on the core lightning plugin, so a
+/// backend can be used also outside the plugin (e.g: tests or
+/// command line tools), and it logs through the `log` facade.
+///
+/// The async interface is provided by `AsyncFolgoreBackend`
+/// (with the `async` feature), that it is implemented by every
+/// `Arc<FolgoreBackend>`.
+pub trait FolgoreBackend {
     /// Return the Backend Kind
     fn kind(&self) -> BackendKind;
-    /// The plugin must respond to getchaininfo with the following fields:
+
+    /// Return the operations served by the backend, by default
+    /// all the operations are native except the dev command.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+    /// Return the information about the chain followed by the backend,
+    /// the plugin responds to `getchaininfo` with the following fields:
     /// - `chain` (string), the network name as introduced in bip70
     /// - `headercount` (number), the number of fetched block headers
     /// - `blockcount` (number), the number of fetched block body
     /// - `ibd` (bool), whether the backend is performing initial block download
-    fn sync_chain_info(&self, _: &mut Plugin<T>, _: Option<u64>) -> Result<Value, PluginError>;
+    fn sync_chain_info(&self, _: Option<u64>) -> Result<ChainInfo, FolgoreError>;
 
     /// Polled by lightningd to get the current feerate, all values must
     /// be passed in sat/kVB.
     ///
-    /// The plugin, if fee estimation succeeds, must respond with the following fields:
-    /// - opening (number), used for funding and also misc transactions
-    /// - mutual_close (number), used for the mutual close transaction
-    /// - unilateral_close (number), used for unilateral close (/commitment) transactions
-    /// - delayed_to_us (number), used for resolving our output from our unilateral close
-    /// - htlc_resolution (number), used for resolving HTLCs after an unilateral close
-    /// - penalty (number), used for resolving revoked transactions
-    /// - min_acceptable (number), used as the minimum acceptable feerate
-    /// - max_acceptable (number), used as the maximum acceptable feerate
-    /// If fee estimation fails, the plugin must set all the fields to null.
-    fn sync_estimate_fees(&self, _: &mut Plugin<T>) -> Result<Value, PluginError>;
+    /// The plugin responds to `estimatefees` with the following fields:
+    /// - `feerate_floor` (number), the minimum acceptable feerate
+    /// - `feerates` (array), the feerate for each `blocks` target, empty
+    ///   if the fee estimation fails.
+    fn sync_estimate_fees(&self) -> Result<FeeEstimates, FolgoreError>;
 
     /// This call takes one parameter, height, which determines the block height of the block to fetch.
-    /// The plugin must set all fields to null if no block was found at the specified height.
+    /// The backend must return `None` if no block was found at the specified height.
     ///
-    /// The plugin must respond to getrawblockbyheight with the following fields:
+    /// The plugin responds to `getrawblockbyheight` with the following fields:
     /// - `blockhash` (string), the block hash as a hexadecimal string
     /// - `block` (string), the block content as a hexadecimal string
-    fn sync_block_by_height(&self, _: &mut Plugin<T>, height: u64) -> Result<Value, PluginError>;
+    fn sync_block_by_height(&self, height: u64) -> Result<Option<RawBlock>, FolgoreError>;
 
     /// This call takes two parameter, the txid (string) and the vout (number) identifying the UTXO we’re interested in.
     ///
-    /// The plugin must set both fields to null if the specified TXO was spent.
+    /// The backend must return `None` if the specified TXO was spent.
     ///
-    /// The plugin must respond to gettxout with the following fields:
+    /// The plugin responds to `getutxout` with the following fields:
     /// - amount (number), the output value in sats
     /// - script (string), the output scriptPubKey
-    fn sync_get_utxo(&self, _: &mut Plugin<T>, _: &str, _: u64) -> Result<Value, PluginError>;
+    fn sync_get_utxo(&self, _: &str, _: u64) -> Result<Option<UtxoOut>, FolgoreError>;
 
     /// This call takes two parameters, a string `tx` representing a hex-encoded
     /// Bitcoin transaction, and a boolean `allowhighfees`, which if set means
     /// suppress any high-fees check implemented in the backend,
     /// since the given transaction may have fees that are very high.
     ///
-    /// The plugin must broadcast it and respond with the following fields:
+    /// The plugin responds to `sendrawtransaction` with the following fields:
     /// - `success` (boolean), which is true if the broadcast succeeded
     /// - `errmsg` (string), if success is false, the reason why it failed
-    fn sync_send_raw_transaction(
-        &self,
-        _: &mut Plugin<T>,
-        _: &str,
-        _: bool,
-    ) -> Result<Value, PluginError>;
+    fn sync_send_raw_transaction(&self, _: &str, _: bool) -> Result<BroadcastResult, FolgoreError>;
 
     /// FIXME: document this dev command if will be merged
-    fn sync_dev_updateutxo(&self, _: &mut Plugin<T>, _: bool) -> Result<Value, PluginError> {
-        Err(error!("unsupported `dev_updateutxo`"))
+    fn sync_dev_updateutxo(&self, _: bool) -> Result<Value, FolgoreError> {
+        Err(FolgoreError::unsupported("unsupported `dev_updateutxo`").with_backend(self.kind()))
     }
 }
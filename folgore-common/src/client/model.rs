@@ -0,0 +1,80 @@
+//! Response models returned by a folgore backend.
+//!
+//! The backends fill these structures, and the plugin
+//! serialize them in the JSON format expected by core
+//! lightning (the same of `bcli`).
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+
+/// Information about the chain followed by the backend.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChainInfo {
+    /// The network name as introduced in bip70 (e.g: `main`).
+    pub chain: String,
+    /// The number of fetched block headers.
+    pub header_count: u64,
+    /// The number of fetched block bodies.
+    pub block_count: u64,
+    /// Whether the backend is performing initial block download.
+    pub ibd: bool,
+}
+
+/// A block at a specific height.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RawBlock {
+    /// The block hash as a hexadecimal string.
+    pub hash: String,
+    /// The block content as a hexadecimal string.
+    pub block: String,
+}
+
+/// The fee rate to confirm a transaction within `blocks`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockFeeRate {
+    pub blocks: u64,
+    /// Fee rate in sat/kVB.
+    pub feerate: u64,
+}
+
+/// Fee estimation of the backend.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeeEstimates {
+    /// The minimum fee rate accepted by the backend, in sat/kVB.
+    pub floor: u64,
+    /// The fee rates ordered by blocks, empty if the backend
+    /// is not able to estimate the fees.
+    pub feerates: Vec<BlockFeeRate>,
+}
+
+/// An unspent transaction output.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UtxoOut {
+    /// The output value in sats.
+    pub amount: u64,
+    /// The output scriptPubKey as hexadecimal string.
+    pub script: String,
+}
+
+/// The result of a transaction broadcast.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BroadcastResult {
+    pub success: bool,
+    /// The reason of the failure, if any.
+    pub errmsg: Option<String>,
+}
+
+impl BroadcastResult {
+    pub fn success() -> Self {
+        Self {
+            success: true,
+            errmsg: None,
+        }
+    }
+
+    pub fn failure<M: std::fmt::Display>(msg: M) -> Self {
+        Self {
+            success: false,
+            errmsg: Some(format!("{msg}")),
+        }
+    }
+}
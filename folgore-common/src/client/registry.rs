@@ -0,0 +1,269 @@
+//! Registry of the backends that can be used by folgore.
+//!
+//! Each backend crate registers its name, the options that it
+//! reads, and a factory that builds the backend from the
+//! configuration. In this way a custom build of the plugin can
+//! link an out-of-tree backend by registering it, without
+//! touching the `BackendKind` enum (see `BackendKind::Custom`).
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde_json::Value;
+
+use crate::client::FolgoreBackend;
+use crate::errors::FolgoreError;
+
+/// An option read by a backend, the fields are the same
+/// required by core lightning to register a plugin option.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BackendOption {
+    pub name: &'static str,
+    /// The option type, `string` or `int`.
+    pub ty: &'static str,
+    pub default: Option<&'static str>,
+    pub description: &'static str,
+}
+
+impl BackendOption {
+    pub fn string(name: &'static str, description: &'static str) -> Self {
+        Self {
+            name,
+            ty: "string",
+            default: None,
+            description,
+        }
+    }
+
+    pub fn int(name: &'static str, description: &'static str) -> Self {
+        Self {
+            name,
+            ty: "int",
+            default: None,
+            description,
+        }
+    }
+}
+
+/// The configuration used to build a backend.
+#[derive(Clone, Debug, Default)]
+pub struct BackendConfig {
+    /// The core lightning network name (e.g: `bitcoin`).
+    pub network: String,
+    /// The core lightning directory.
+    pub lightning_dir: String,
+    /// The path of the core lightning RPC socket.
+    pub rpc_path: String,
+    /// The value of the options set by the user.
+    pub options: HashMap<String, Value>,
+}
+
+impl BackendConfig {
+    /// Return the option as a not empty string.
+    pub fn get_str(&self, name: &str) -> Option<String> {
+        match self.options.get(name)? {
+            Value::String(value) if !value.trim().is_empty() => Some(value.trim().to_owned()),
+            Value::Number(value) => Some(value.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Return the option as a number, the option is
+    /// invalid if it is set but it is not a number.
+    pub fn get_u64(&self, name: &str) -> Result<Option<u64>, FolgoreError> {
+        let value = match self.options.get(name) {
+            None | Some(Value::Null) => return Ok(None),
+            Some(Value::Number(value)) => value.as_u64(),
+            Some(Value::String(value)) => value.trim().parse().ok(),
+            Some(_) => None,
+        };
+        value
+            .map(Some)
+            .ok_or(FolgoreError::validation(format!("invalid `{name}` value")))
+    }
+}
+
+/// Build a backend from the configuration and the recovery strategy,
+/// the backend can be shared between threads.
+pub type BackendFactory<S> = Box<
+    dyn Fn(&BackendConfig, Arc<S>) -> Result<Arc<dyn FolgoreBackend + Send + Sync>, FolgoreError>
+        + Send
+        + Sync,
+>;
+
+struct BackendEntry<S> {
+    name: &'static str,
+    options: Vec<BackendOption>,
+    factory: BackendFactory<S>,
+}
+
+/// Registry of the backends, `S` is the recovery strategy
+/// given to each backend.
+pub struct BackendRegistry<S> {
+    backends: Vec<BackendEntry<S>>,
+}
+
+impl<S> Default for BackendRegistry<S> {
+    fn default() -> Self {
+        Self { backends: vec![] }
+    }
+}
+
+impl<S> BackendRegistry<S> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a new backend, fails if a backend with the
+    /// same name is already registered.
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        options: Vec<BackendOption>,
+        factory: BackendFactory<S>,
+    ) -> Result<&mut Self, FolgoreError> {
+        if self.contains(name) {
+            return Err(FolgoreError::validation(format!(
+                "client {name} already registered"
+            )));
+        }
+        self.backends.push(BackendEntry {
+            name,
+            options,
+            factory,
+        });
+        Ok(self)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.backends.iter().any(|entry| entry.name == name)
+    }
+
+    /// The names of the registered backends.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.backends.iter().map(|entry| entry.name).collect()
+    }
+
+    /// The options of all the backends, an option shared by
+    /// more backends is returned only once.
+    pub fn options(&self) -> Vec<BackendOption> {
+        let mut options: Vec<BackendOption> = vec![];
+        for option in self.backends.iter().flat_map(|entry| entry.options.iter()) {
+            if !options.iter().any(|known| known.name == option.name) {
+                options.push(option.clone());
+            }
+        }
+        options
+    }
+
+    /// Build the backend with the `name`.
+    pub fn create(
+        &self,
+        name: &str,
+        config: &BackendConfig,
+        strategy: Arc<S>,
+    ) -> Result<Arc<dyn FolgoreBackend + Send + Sync>, FolgoreError> {
+        let entry = self
+            .backends
+            .iter()
+            .find(|entry| entry.name == name)
+            .ok_or(FolgoreError::unsupported(format!(
+                "client {name} not supported"
+            )))?;
+        (entry.factory)(config, strategy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::client::{BackendKind, BroadcastResult, ChainInfo, FeeEstimates, RawBlock, UtxoOut};
+    use crate::errors::ErrorKind;
+
+    struct Custom;
+
+    impl FolgoreBackend for Custom {
+        fn kind(&self) -> BackendKind {
+            BackendKind::Custom("custom")
+        }
+
+        fn sync_chain_info(&self, _: Option<u64>) -> Result<ChainInfo, FolgoreError> {
+            Err(FolgoreError::network("offline"))
+        }
+
+        fn sync_estimate_fees(&self) -> Result<FeeEstimates, FolgoreError> {
+            Err(FolgoreError::network("offline"))
+        }
+
+        fn sync_block_by_height(&self, _: u64) -> Result<Option<RawBlock>, FolgoreError> {
+            Ok(None)
+        }
+
+        fn sync_get_utxo(&self, _: &str, _: u64) -> Result<Option<UtxoOut>, FolgoreError> {
+            Ok(None)
+        }
+
+        fn sync_send_raw_transaction(
+            &self,
+            _: &str,
+            _: bool,
+        ) -> Result<BroadcastResult, FolgoreError> {
+            Ok(BroadcastResult::success())
+        }
+    }
+
+    fn factory() -> BackendFactory<()> {
+        Box::new(|config, _| {
+            config
+                .get_str("custom-url")
+                .ok_or(FolgoreError::validation("custom url not specified"))?;
+            Ok(Arc::new(Custom))
+        })
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = BackendRegistry::<()>::new();
+        let url = BackendOption::string("custom-url", "custom url");
+        assert!(registry
+            .register("custom", vec![url.clone()], factory())
+            .is_ok());
+        assert!(registry
+            .register("other", vec![url.clone()], factory())
+            .is_ok());
+        assert!(registry.register("custom", vec![], factory()).is_err());
+        assert_eq!(registry.names(), vec!["custom", "other"]);
+        assert_eq!(registry.options(), vec![url]);
+
+        let mut config = BackendConfig::default();
+        let err = registry.create("custom", &config, Arc::new(())).err();
+        assert_eq!(err.map(|err| err.kind()), Some(ErrorKind::Validation));
+        config
+            .options
+            .insert("custom-url".to_owned(), json!("http://localhost"));
+        let client = registry.create("custom", &config, Arc::new(())).ok();
+        assert_eq!(
+            client.map(|client| client.kind().to_string()),
+            Some("custom".to_owned())
+        );
+        let err = registry.create("unknown", &config, Arc::new(())).err();
+        assert_eq!(err.map(|err| err.kind()), Some(ErrorKind::Unsupported));
+    }
+
+    #[test]
+    fn test_config_options() {
+        let mut config = BackendConfig::default();
+        config.options.insert("timeout".to_owned(), json!(30));
+        config.options.insert("burst".to_owned(), json!("10"));
+        config.options.insert("invalid".to_owned(), json!("ten"));
+        config.options.insert("empty".to_owned(), json!(" "));
+        assert_eq!(config.get_u64("timeout").ok(), Some(Some(30)));
+        assert_eq!(config.get_u64("burst").ok(), Some(Some(10)));
+        assert_eq!(config.get_u64("missing").ok(), Some(None));
+        assert!(config.get_u64("invalid").is_err());
+        assert_eq!(config.get_str("empty"), None);
+    }
+}
@@ -0,0 +1,533 @@
+//! Record and replay the responses of a backend.
+//!
+//! `RecordBackend` wraps a backend and appends each request
+//! with its response to a trace file (a JSON object per line),
+//! so a bug report can ship the exact responses seen by the
+//! user. `ReplayBackend` serves the responses of a trace
+//! without network: the responses to the same request are
+//! returned in the recorded order, and the last one is
+//! repeated when the trace has no more responses.
+//!
+//! A line of the trace looks like the following one, where
+//! `result` is the JSON returned to core lightning, or `error`
+//! if the backend failed:
+//!
+//! `{"backend":"esplora","method":"getutxout","params":{"txid":"...","vout":0},"result":{"amount":null,"script":null},"time":1700000000}`
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::client::{
+    bcli, BackendConfig, BackendKind, BackendOption, BackendRegistry, BlockFeeRate,
+    BroadcastResult, Capabilities, ChainInfo, FeeEstimates, FolgoreBackend, Operation, RawBlock,
+    UtxoOut,
+};
+use crate::errors::{ErrorKind, FolgoreError};
+
+/// The trace file used when the user does not specify one.
+pub const DEFAULT_TRACE_FILE: &str = "folgore-trace.jsonl";
+
+/// The trace file inside the core lightning directory,
+/// an absolute `file` is used as it is.
+pub fn trace_path(config: &BackendConfig, file: &str) -> PathBuf {
+    Path::new(&config.lightning_dir).join(file)
+}
+
+/// Append the traffic of the `inner` backend to a trace file.
+pub struct RecordBackend {
+    inner: Arc<dyn FolgoreBackend + Send + Sync>,
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl RecordBackend {
+    pub fn new<P: AsRef<Path>>(
+        inner: Arc<dyn FolgoreBackend + Send + Sync>,
+        path: P,
+    ) -> Result<Self, FolgoreError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|err| {
+                FolgoreError::validation(format!(
+                    "impossible open the trace `{}`: {err}",
+                    path.display()
+                ))
+                .with_source(err)
+            })?;
+        Ok(Self {
+            inner,
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write the entry in the trace, a failure is logged and
+    /// does not change the response of the backend.
+    fn record<T>(
+        &self,
+        method: Operation,
+        params: Value,
+        result: &Result<T, FolgoreError>,
+        encode: fn(&T) -> Value,
+    ) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default();
+        let mut entry = json!({
+            "backend": self.inner.kind().to_string(),
+            "method": method.to_string(),
+            "params": params,
+            "time": time,
+        });
+        match result {
+            Ok(response) => entry["result"] = encode(response),
+            Err(err) => {
+                entry["error"] = json!({
+                    "kind": err.kind().to_string(),
+                    "backend": err.backend().map(|backend| backend.to_string()),
+                    "message": err.to_string(),
+                })
+            }
+        }
+        let mut file = self
+            .file
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Err(err) = writeln!(file, "{entry}") {
+            log::warn!(
+                "impossible write the trace `{}`: {err}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+impl FolgoreBackend for RecordBackend {
+    fn kind(&self) -> BackendKind {
+        self.inner.kind()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn sync_chain_info(&self, last_height: Option<u64>) -> Result<ChainInfo, FolgoreError> {
+        let result = self.inner.sync_chain_info(last_height);
+        let params = json!({ "last_height": last_height });
+        self.record(Operation::ChainInfo, params, &result, bcli::chain_info);
+        result
+    }
+
+    fn sync_estimate_fees(&self) -> Result<FeeEstimates, FolgoreError> {
+        let result = self.inner.sync_estimate_fees();
+        self.record(
+            Operation::EstimateFees,
+            json!({}),
+            &result,
+            bcli::estimate_fees,
+        );
+        result
+    }
+
+    fn sync_block_by_height(&self, height: u64) -> Result<Option<RawBlock>, FolgoreError> {
+        let result = self.inner.sync_block_by_height(height);
+        let params = json!({ "height": height });
+        self.record(Operation::BlockByHeight, params, &result, |block| {
+            bcli::raw_block(block.as_ref())
+        });
+        result
+    }
+
+    fn sync_get_utxo(&self, txid: &str, vout: u64) -> Result<Option<UtxoOut>, FolgoreError> {
+        let result = self.inner.sync_get_utxo(txid, vout);
+        let params = json!({ "txid": txid, "vout": vout });
+        self.record(Operation::GetUtxo, params, &result, |utxo| {
+            bcli::utxo(utxo.as_ref())
+        });
+        result
+    }
+
+    fn sync_send_raw_transaction(
+        &self,
+        tx: &str,
+        allow_high_fee: bool,
+    ) -> Result<BroadcastResult, FolgoreError> {
+        let result = self.inner.sync_send_raw_transaction(tx, allow_high_fee);
+        let params = json!({ "tx": tx, "allowhighfees": allow_high_fee });
+        self.record(
+            Operation::SendRawTransaction,
+            params,
+            &result,
+            bcli::send_raw_transaction,
+        );
+        result
+    }
+
+    fn sync_dev_updateutxo(&self, iamsure: bool) -> Result<Value, FolgoreError> {
+        let result = self.inner.sync_dev_updateutxo(iamsure);
+        let params = json!({ "iamsure": iamsure });
+        self.record(Operation::DevUpdateUtxo, params, &result, Value::clone);
+        result
+    }
+}
+
+/// A response of the trace, the error is kept in pieces
+/// because `FolgoreError` can not be cloned.
+#[derive(Clone, Debug)]
+enum Response {
+    Ok(Value),
+    Err {
+        kind: ErrorKind,
+        backend: Option<BackendKind>,
+        msg: String,
+    },
+}
+
+/// Serve the responses recorded by a `RecordBackend`.
+pub struct ReplayBackend {
+    /// The responses by method and params.
+    responses: Mutex<HashMap<(Operation, String), VecDeque<Response>>>,
+}
+
+impl ReplayBackend {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, FolgoreError> {
+        let path = path.as_ref();
+        let trace = std::fs::read_to_string(path).map_err(|err| {
+            FolgoreError::validation(format!(
+                "impossible read the trace `{}`: {err}",
+                path.display()
+            ))
+            .with_source(err)
+        })?;
+        Self::from_trace(&trace)
+    }
+
+    /// Load the trace content, one JSON object per line.
+    pub fn from_trace(trace: &str) -> Result<Self, FolgoreError> {
+        let mut responses: HashMap<_, VecDeque<_>> = HashMap::new();
+        for (idx, line) in trace.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Value = serde_json::from_str(line)?;
+            let invalid = |field: &str| {
+                FolgoreError::validation(format!(
+                    "invalid field `{field}` at line {} of the trace",
+                    idx + 1
+                ))
+            };
+            let method = entry["method"].as_str().ok_or(invalid("method"))?;
+            let method = Operation::try_from(method)?;
+            let response = match (entry.get("result"), entry.get("error")) {
+                (Some(result), None) => Response::Ok(result.clone()),
+                (None, Some(err)) => Response::Err {
+                    kind: ErrorKind::try_from(err["kind"].as_str().unwrap_or_default())?,
+                    // the backend that recorded the error if the error has none
+                    backend: err["backend"]
+                        .as_str()
+                        .or(entry["backend"].as_str())
+                        .and_then(|backend| BackendKind::try_from(backend).ok()),
+                    msg: err["message"].as_str().unwrap_or_default().to_owned(),
+                },
+                _ => return Err(invalid("result")),
+            };
+            responses
+                .entry((method, params_key(&entry["params"])))
+                .or_default()
+                .push_back(response);
+        }
+        Ok(Self {
+            responses: Mutex::new(responses),
+        })
+    }
+
+    /// The next response to the request.
+    fn next(&self, method: Operation, params: Value) -> Result<Value, FolgoreError> {
+        let mut responses = self
+            .responses
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let key = params_key(&params);
+        let response = responses
+            .get_mut(&(method, key))
+            .and_then(|queue| {
+                if queue.len() > 1 {
+                    queue.pop_front()
+                } else {
+                    queue.front().cloned()
+                }
+            })
+            .ok_or(
+                FolgoreError::not_found(format!(
+                    "no recorded response to `{method}` with params {params}"
+                ))
+                .with_backend(self.kind()),
+            )?;
+        match response {
+            Response::Ok(value) => Ok(value),
+            Response::Err { kind, backend, msg } => {
+                Err(FolgoreError::new(kind, msg).with_backend(backend.unwrap_or(self.kind())))
+            }
+        }
+    }
+}
+
+/// The params without the missing fields, so `null` and
+/// a missing field are the same request.
+fn params_key(params: &Value) -> String {
+    match params {
+        Value::Object(params) => {
+            let params = params
+                .iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect::<serde_json::Map<_, _>>();
+            Value::Object(params).to_string()
+        }
+        Value::Null => "{}".to_owned(),
+        params => params.to_string(),
+    }
+}
+
+fn field<'a, T>(
+    value: &'a Value,
+    key: &str,
+    get: fn(&'a Value) -> Option<T>,
+) -> Result<T, FolgoreError> {
+    get(&value[key]).ok_or(FolgoreError::invalid_response(format!(
+        "invalid field `{key}` in the recorded response `{value}`"
+    )))
+}
+
+impl FolgoreBackend for ReplayBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Custom("replay")
+    }
+
+    /// All the operations can be recorded.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::native()
+    }
+
+    fn sync_chain_info(&self, last_height: Option<u64>) -> Result<ChainInfo, FolgoreError> {
+        let value = self.next(Operation::ChainInfo, json!({ "last_height": last_height }))?;
+        Ok(ChainInfo {
+            chain: field(&value, "chain", Value::as_str)?.to_owned(),
+            header_count: field(&value, "headercount", Value::as_u64)?,
+            block_count: field(&value, "blockcount", Value::as_u64)?,
+            ibd: field(&value, "ibd", Value::as_bool)?,
+        })
+    }
+
+    fn sync_estimate_fees(&self) -> Result<FeeEstimates, FolgoreError> {
+        let value = self.next(Operation::EstimateFees, json!({}))?;
+        let mut feerates = vec![];
+        for feerate in field(&value, "feerates", Value::as_array)? {
+            feerates.push(BlockFeeRate {
+                blocks: field(feerate, "blocks", Value::as_u64)?,
+                feerate: field(feerate, "feerate", Value::as_u64)?,
+            });
+        }
+        Ok(FeeEstimates {
+            floor: field(&value, "feerate_floor", Value::as_u64)?,
+            feerates,
+        })
+    }
+
+    fn sync_block_by_height(&self, height: u64) -> Result<Option<RawBlock>, FolgoreError> {
+        let value = self.next(Operation::BlockByHeight, json!({ "height": height }))?;
+        if value["blockhash"].is_null() {
+            return Ok(None);
+        }
+        Ok(Some(RawBlock {
+            hash: field(&value, "blockhash", Value::as_str)?.to_owned(),
+            block: field(&value, "block", Value::as_str)?.to_owned(),
+        }))
+    }
+
+    fn sync_get_utxo(&self, txid: &str, vout: u64) -> Result<Option<UtxoOut>, FolgoreError> {
+        let value = self.next(Operation::GetUtxo, json!({ "txid": txid, "vout": vout }))?;
+        if value["amount"].is_null() {
+            return Ok(None);
+        }
+        Ok(Some(UtxoOut {
+            amount: field(&value, "amount", Value::as_u64)?,
+            script: field(&value, "script", Value::as_str)?.to_owned(),
+        }))
+    }
+
+    fn sync_send_raw_transaction(
+        &self,
+        tx: &str,
+        allow_high_fee: bool,
+    ) -> Result<BroadcastResult, FolgoreError> {
+        let value = self.next(
+            Operation::SendRawTransaction,
+            json!({ "tx": tx, "allowhighfees": allow_high_fee }),
+        )?;
+        let errmsg = field(&value, "errmsg", Value::as_str)?;
+        Ok(BroadcastResult {
+            success: field(&value, "success", Value::as_bool)?,
+            errmsg: (!errmsg.is_empty()).then(|| errmsg.to_owned()),
+        })
+    }
+
+    fn sync_dev_updateutxo(&self, iamsure: bool) -> Result<Value, FolgoreError> {
+        self.next(Operation::DevUpdateUtxo, json!({ "iamsure": iamsure }))
+    }
+}
+
+pub fn options() -> Vec<BackendOption> {
+    vec![BackendOption::string(
+        "bitcoin-replay-file",
+        "The trace served by the `replay` client, relative to the lightning dir (by default `folgore-trace.jsonl`)",
+    )]
+}
+
+/// Register the `replay` client, it does not use the
+/// recovery strategy because it never goes on the network.
+pub fn register<S>(registry: &mut BackendRegistry<S>) -> Result<(), FolgoreError> {
+    registry.register(
+        "replay",
+        options(),
+        Box::new(|config, _| {
+            let file = config
+                .get_str("bitcoin-replay-file")
+                .unwrap_or(DEFAULT_TRACE_FILE.to_owned());
+            Ok(Arc::new(ReplayBackend::open(trace_path(config, &file))?))
+        }),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answer with an error until the first block is requested.
+    struct Flaky;
+
+    impl FolgoreBackend for Flaky {
+        fn kind(&self) -> BackendKind {
+            BackendKind::Esplora
+        }
+
+        fn sync_chain_info(&self, _: Option<u64>) -> Result<ChainInfo, FolgoreError> {
+            Ok(ChainInfo {
+                chain: "test".to_owned(),
+                header_count: 10,
+                block_count: 9,
+                ibd: false,
+            })
+        }
+
+        fn sync_estimate_fees(&self) -> Result<FeeEstimates, FolgoreError> {
+            Err(FolgoreError::rate_limited("too many requests"))
+        }
+
+        fn sync_block_by_height(&self, height: u64) -> Result<Option<RawBlock>, FolgoreError> {
+            Ok((height < 10).then(|| RawBlock {
+                hash: format!("hash{height}"),
+                block: format!("block{height}"),
+            }))
+        }
+
+        fn sync_get_utxo(&self, _: &str, vout: u64) -> Result<Option<UtxoOut>, FolgoreError> {
+            Ok((vout == 0).then(|| UtxoOut {
+                amount: 1000,
+                script: "51".to_owned(),
+            }))
+        }
+
+        fn sync_send_raw_transaction(
+            &self,
+            _: &str,
+            _: bool,
+        ) -> Result<BroadcastResult, FolgoreError> {
+            Ok(BroadcastResult::failure("bad-txns-inputs-missingorspent"))
+        }
+    }
+
+    #[test]
+    fn test_record_replay() {
+        let path = std::env::temp_dir().join(format!("folgore-trace-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let Ok(record) = RecordBackend::new(Arc::new(Flaky), &path) else {
+            panic!("impossible open the trace");
+        };
+        let info = record.sync_chain_info(None).ok();
+        let fees = record.sync_estimate_fees().is_err();
+        let blocks = (9..11)
+            .map(|height| record.sync_block_by_height(height).ok())
+            .collect::<Vec<_>>();
+        let utxos = (0..2)
+            .map(|vout| record.sync_get_utxo("txid", vout).ok())
+            .collect::<Vec<_>>();
+        let broadcast = record.sync_send_raw_transaction("tx", false).ok();
+
+        let replay = ReplayBackend::open(&path);
+        let _ = std::fs::remove_file(&path);
+        let Ok(replay) = replay else {
+            panic!("impossible replay the trace");
+        };
+        assert_eq!(replay.sync_chain_info(None).ok(), info);
+        let Err(err) = replay.sync_estimate_fees() else {
+            panic!("the error is not replayed");
+        };
+        assert!(fees);
+        assert_eq!(err.kind(), ErrorKind::RateLimited);
+        assert_eq!(err.backend(), Some(BackendKind::Esplora));
+        for (height, block) in (9..11).zip(blocks) {
+            assert_eq!(replay.sync_block_by_height(height).ok(), block);
+        }
+        for (vout, utxo) in (0..2).zip(utxos) {
+            assert_eq!(replay.sync_get_utxo("txid", vout).ok(), utxo);
+        }
+        assert_eq!(
+            replay.sync_send_raw_transaction("tx", false).ok(),
+            broadcast
+        );
+        // the request was not recorded
+        let Err(err) = replay.sync_block_by_height(1) else {
+            panic!("the block 1 is not recorded");
+        };
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_replay_order() {
+        let trace = [
+            json!({ "backend": "esplora", "method": "estimatefees", "params": {}, "error": { "kind": "network", "message": "offline" } }),
+            json!({ "backend": "esplora", "method": "estimatefees", "params": {}, "result": { "feerate_floor": 1000, "feerates": [] } }),
+        ]
+        .map(|entry| entry.to_string())
+        .join("\n");
+        let Ok(replay) = ReplayBackend::from_trace(&trace) else {
+            panic!("invalid trace");
+        };
+        assert!(replay.sync_estimate_fees().is_err());
+        // the last response is repeated
+        for _ in 0..2 {
+            let Ok(fees) = replay.sync_estimate_fees() else {
+                panic!("the fees are not replayed");
+            };
+            assert_eq!(fees.floor, 1000);
+        }
+        assert!(ReplayBackend::from_trace("{ \"method\": \"unknown\" }").is_err());
+    }
+}
@@ -0,0 +1,389 @@
+//! Conformance suite of the `bcli` contract.
+//!
+//! Any `FolgoreBackend` that serves the deterministic chain
+//! described by a `Fixture` can be checked with `run`, the
+//! suite serializes the responses like the plugin does and
+//! asserts the exact schema returned to core lightning,
+//! including the null semantics of missing blocks and spent
+//! outputs.
+//!
+//! The `check_*` functions work on the JSON, so they can be
+//! used also on the responses of a running plugin.
+//!
+//! The suite is available with the `conformance` feature, that
+//! the backend crates enable only in the `dev-dependencies`, the
+//! `Fixture` lives in `crate::fixture` because the mock backend
+//! serves it outside the tests too.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::fmt;
+
+use serde_json::{Map, Value};
+
+use crate::client::{bcli, FolgoreBackend, Operation};
+use crate::errors::FolgoreError;
+
+pub use crate::fixture::{self, Fixture, FixtureBlock, FixtureOutput};
+
+/// A response that does not respect the `bcli` contract.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    pub method: Operation,
+    pub msg: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}`: {}", self.method, self.msg)
+    }
+}
+
+/// Run the suite against the backend and return all the
+/// violations found, the operations that the backend does
+/// not support are skipped.
+pub fn run(backend: &dyn FolgoreBackend, fixture: &Fixture) -> Vec<Violation> {
+    let capabilities = backend.capabilities();
+    let mut violations = vec![];
+    let mut check = |method: Operation, result: Result<(), String>| {
+        if let Err(msg) = result {
+            violations.push(Violation { method, msg });
+        }
+    };
+
+    if capabilities.supports(Operation::ChainInfo) {
+        let response = backend
+            .sync_chain_info(None)
+            .map(|info| bcli::chain_info(&info));
+        check(
+            Operation::ChainInfo,
+            response
+                .map_err(backend_error)
+                .and_then(|value| check_chain_info(&value, fixture)),
+        );
+    }
+
+    if capabilities.supports(Operation::EstimateFees) {
+        let response = backend
+            .sync_estimate_fees()
+            .map(|fees| bcli::estimate_fees(&fees));
+        check(
+            Operation::EstimateFees,
+            response
+                .map_err(backend_error)
+                .and_then(|value| check_estimate_fees(&value)),
+        );
+    }
+
+    if capabilities.supports(Operation::BlockByHeight) {
+        // the block after the tip must be null
+        for height in 0..=fixture.tip() + 1 {
+            let response = backend
+                .sync_block_by_height(height)
+                .map(|block| bcli::raw_block(block.as_ref()));
+            check(
+                Operation::BlockByHeight,
+                response
+                    .map_err(backend_error)
+                    .and_then(|value| check_raw_block(&value, fixture.block(height)))
+                    .map_err(|err| format!("height {height}: {err}")),
+            );
+        }
+    }
+
+    if capabilities.supports(Operation::GetUtxo) {
+        for (output, unspent) in [(&fixture.unspent, true), (&fixture.spent, false)] {
+            let response = backend
+                .sync_get_utxo(&output.txid, output.vout)
+                .map(|utxo| bcli::utxo(utxo.as_ref()));
+            check(
+                Operation::GetUtxo,
+                response
+                    .map_err(backend_error)
+                    .and_then(|value| check_utxo(&value, unspent.then_some(output)))
+                    .map_err(|err| format!("{}:{}: {err}", output.txid, output.vout)),
+            );
+        }
+    }
+
+    if capabilities.supports(Operation::SendRawTransaction) {
+        let response = backend
+            .sync_send_raw_transaction(&fixture.rejected_tx, false)
+            .map(|result| bcli::send_raw_transaction(&result));
+        check(
+            Operation::SendRawTransaction,
+            response
+                .map_err(backend_error)
+                .and_then(|value| check_send_raw_transaction(&value, false)),
+        );
+    }
+    violations
+}
+
+/// Run the suite and panic with all the violations found.
+pub fn assert_conformance(backend: &dyn FolgoreBackend, fixture: &Fixture) {
+    let violations = run(backend, fixture);
+    if !violations.is_empty() {
+        let violations = violations
+            .iter()
+            .map(|violation| violation.to_string())
+            .collect::<Vec<_>>();
+        panic!(
+            "client `{}` violates the bcli contract:\n{}",
+            backend.kind(),
+            violations.join("\n")
+        );
+    }
+}
+
+fn backend_error(err: FolgoreError) -> String {
+    format!("backend error: {err}")
+}
+
+/// Check that the response is an object with exactly the `keys`.
+fn object<'a>(value: &'a Value, keys: &[&str]) -> Result<&'a Map<String, Value>, String> {
+    let Some(object) = value.as_object() else {
+        return Err(format!("expected an object, got `{value}`"));
+    };
+    for key in keys {
+        if !object.contains_key(*key) {
+            return Err(format!("missing field `{key}` in `{value}`"));
+        }
+    }
+    if let Some(key) = object.keys().find(|key| !keys.contains(&key.as_str())) {
+        return Err(format!("unexpected field `{key}` in `{value}`"));
+    }
+    Ok(object)
+}
+
+fn number(object: &Map<String, Value>, key: &str) -> Result<u64, String> {
+    object[key]
+        .as_u64()
+        .ok_or(format!("field `{key}` is not a number: `{}`", object[key]))
+}
+
+fn string<'a>(object: &'a Map<String, Value>, key: &str) -> Result<&'a str, String> {
+    object[key]
+        .as_str()
+        .ok_or(format!("field `{key}` is not a string: `{}`", object[key]))
+}
+
+/// Both fields must be null, or both must be set.
+fn nullable_pair(object: &Map<String, Value>, first: &str, second: &str) -> Result<bool, String> {
+    match (object[first].is_null(), object[second].is_null()) {
+        (true, true) => Ok(true),
+        (false, false) => Ok(false),
+        _ => Err(format!(
+            "`{first}` and `{second}` must be both null or both set"
+        )),
+    }
+}
+
+fn expect<T: PartialEq + fmt::Display + ?Sized>(
+    field: &str,
+    value: &T,
+    expected: &T,
+) -> Result<(), String> {
+    if value != expected {
+        return Err(format!(
+            "field `{field}` is `{value}` instead of `{expected}`"
+        ));
+    }
+    Ok(())
+}
+
+pub fn check_chain_info(value: &Value, fixture: &Fixture) -> Result<(), String> {
+    let object = object(value, &["chain", "headercount", "blockcount", "ibd"])?;
+    expect("chain", string(object, "chain")?, fixture.chain.as_str())?;
+    let blocks = number(object, "blockcount")?;
+    expect("blockcount", &blocks, &fixture.tip())?;
+    let headers = number(object, "headercount")?;
+    if headers < blocks {
+        return Err(format!(
+            "`headercount` {headers} is lower than `blockcount` {blocks}"
+        ));
+    }
+    if !object["ibd"].is_boolean() {
+        return Err(format!("field `ibd` is not a boolean: `{}`", object["ibd"]));
+    }
+    Ok(())
+}
+
+/// The fee rates are not part of the fixture, so only the
+/// schema is checked.
+pub fn check_estimate_fees(value: &Value) -> Result<(), String> {
+    let object = object(value, &["feerate_floor", "feerates"])?;
+    number(object, "feerate_floor")?;
+    let Some(feerates) = object["feerates"].as_array() else {
+        return Err(format!(
+            "field `feerates` is not an array: `{}`",
+            object["feerates"]
+        ));
+    };
+    for feerate in feerates {
+        let feerate = self::object(feerate, &["blocks", "feerate"])?;
+        number(feerate, "blocks")?;
+        number(feerate, "feerate")?;
+    }
+    Ok(())
+}
+
+pub fn check_raw_block(value: &Value, expected: Option<&FixtureBlock>) -> Result<(), String> {
+    let object = object(value, &["blockhash", "block"])?;
+    let null = nullable_pair(object, "blockhash", "block")?;
+    match expected {
+        Some(_) if null => Err("the block is null".to_owned()),
+        Some(block) => {
+            expect(
+                "blockhash",
+                string(object, "blockhash")?,
+                block.hash.as_str(),
+            )?;
+            expect("block", string(object, "block")?, block.raw.as_str())
+        }
+        None if !null => Err("the block must be null".to_owned()),
+        None => Ok(()),
+    }
+}
+
+pub fn check_utxo(value: &Value, expected: Option<&FixtureOutput>) -> Result<(), String> {
+    let object = object(value, &["amount", "script"])?;
+    let null = nullable_pair(object, "amount", "script")?;
+    match expected {
+        Some(_) if null => Err("the unspent output is null".to_owned()),
+        Some(output) => {
+            expect("amount", &number(object, "amount")?, &output.amount)?;
+            expect("script", string(object, "script")?, output.script.as_str())
+        }
+        None if !null => Err("the spent output must be null".to_owned()),
+        None => Ok(()),
+    }
+}
+
+/// On failure `errmsg` must explain the reason.
+pub fn check_send_raw_transaction(value: &Value, success: bool) -> Result<(), String> {
+    let object = object(value, &["success", "errmsg"])?;
+    let Some(result) = object["success"].as_bool() else {
+        return Err(format!(
+            "field `success` is not a boolean: `{}`",
+            object["success"]
+        ));
+    };
+    expect("success", &result, &success)?;
+    let errmsg = string(object, "errmsg")?;
+    if !success && errmsg.is_empty() {
+        return Err("field `errmsg` is empty on failure".to_owned());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::client::{BackendKind, BroadcastResult, ChainInfo, FeeEstimates, RawBlock, UtxoOut};
+
+    /// Serve the fixture chain, `broken` returns the
+    /// tip also after it and accepts all the transactions.
+    struct FixtureBackend {
+        fixture: Fixture,
+        broken: bool,
+    }
+
+    impl FolgoreBackend for FixtureBackend {
+        fn kind(&self) -> BackendKind {
+            BackendKind::Custom("fixture")
+        }
+
+        fn sync_chain_info(&self, _: Option<u64>) -> Result<ChainInfo, FolgoreError> {
+            Ok(ChainInfo {
+                chain: self.fixture.chain.clone(),
+                header_count: self.fixture.tip(),
+                block_count: self.fixture.tip(),
+                ibd: false,
+            })
+        }
+
+        fn sync_estimate_fees(&self) -> Result<FeeEstimates, FolgoreError> {
+            Ok(FeeEstimates {
+                floor: 1000,
+                feerates: vec![],
+            })
+        }
+
+        fn sync_block_by_height(&self, height: u64) -> Result<Option<RawBlock>, FolgoreError> {
+            let height = if self.broken {
+                height.min(self.fixture.tip())
+            } else {
+                height
+            };
+            Ok(self.fixture.block(height).map(|block| RawBlock {
+                hash: block.hash.clone(),
+                block: block.raw.clone(),
+            }))
+        }
+
+        fn sync_get_utxo(&self, txid: &str, vout: u64) -> Result<Option<UtxoOut>, FolgoreError> {
+            let output = &self.fixture.unspent;
+            if output.txid != txid || output.vout != vout {
+                return Ok(None);
+            }
+            Ok(Some(UtxoOut {
+                amount: output.amount,
+                script: output.script.clone(),
+            }))
+        }
+
+        fn sync_send_raw_transaction(
+            &self,
+            tx: &str,
+            _: bool,
+        ) -> Result<BroadcastResult, FolgoreError> {
+            if !self.broken && tx == self.fixture.rejected_tx {
+                return Ok(BroadcastResult::failure("bad-txns-inputs-missingorspent"));
+            }
+            Ok(BroadcastResult::success())
+        }
+    }
+
+    #[test]
+    fn test_conformance() {
+        let fixture = Fixture::regtest(10);
+        assert_eq!(fixture, Fixture::regtest(10));
+        assert_eq!(fixture.tip(), 9);
+        assert_eq!(fixture.blocks[0].hash, fixture::REGTEST_GENESIS_HASH);
+        assert!(fixture.blocks[1].raw.contains(&fixture.blocks[1].coinbase));
+
+        let backend = FixtureBackend {
+            fixture: fixture.clone(),
+            broken: false,
+        };
+        assert_conformance(&backend, &fixture);
+
+        let backend = FixtureBackend {
+            fixture: fixture.clone(),
+            broken: true,
+        };
+        let methods = run(&backend, &fixture)
+            .into_iter()
+            .map(|violation| violation.method)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            methods,
+            vec![Operation::BlockByHeight, Operation::SendRawTransaction]
+        );
+    }
+
+    #[test]
+    fn test_schema() {
+        assert!(check_estimate_fees(&json!({ "feerate_floor": 1000, "feerates": {} })).is_err());
+        assert!(check_estimate_fees(&json!({ "feerate_floor": 1000, "feerates": [] })).is_ok());
+        assert!(check_utxo(&json!({ "amount": 1, "script": null }), None).is_err());
+        assert!(check_utxo(&json!({ "amount": null }), None).is_err());
+        assert!(check_raw_block(
+            &json!({ "blockhash": null, "block": null, "extra": 1 }),
+            None
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! Error type shared by all the folgore backends.
+//!
+//! The backends classify their errors with an `ErrorKind`,
+//! so the plugin can decide what to do (retry, use the fallback
+//! client, or give up) without looking at the error message,
+//! and the mapping to the core lightning error codes lives in
+//! a single place.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::error::Error as StdError;
+use std::fmt;
+
+use clightningrpc_plugin::errors::PluginError;
+use serde_json::json;
+
+use crate::client::BackendKind;
+use crate::stragegy::Transient;
+
+/// The kind of error returned by a backend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The backend is not reachable (e.g: connection refused,
+    /// timeout, server error).
+    Network,
+    /// The backend refused our credentials.
+    Auth,
+    /// The resource requested does not exist.
+    NotFound,
+    /// The backend asked us to slow down.
+    RateLimited,
+    /// The backend answered with something that we are
+    /// not able to understand.
+    InvalidResponse,
+    /// The request is not valid (e.g: a malformed transaction).
+    Validation,
+    /// The operation is not supported by the backend.
+    Unsupported,
+}
+
+impl ErrorKind {
+    /// The error code returned to core lightning, the `-1`
+    /// is left to the generic plugin errors.
+    pub fn code(&self) -> i32 {
+        match self {
+            Self::Network => -2,
+            Self::Auth => -3,
+            Self::NotFound => -4,
+            Self::RateLimited => -5,
+            Self::InvalidResponse => -6,
+            Self::Validation => -7,
+            Self::Unsupported => -8,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Network => write!(f, "network"),
+            Self::Auth => write!(f, "auth"),
+            Self::NotFound => write!(f, "not-found"),
+            Self::RateLimited => write!(f, "rate-limited"),
+            Self::InvalidResponse => write!(f, "invalid-response"),
+            Self::Validation => write!(f, "validation"),
+            Self::Unsupported => write!(f, "unsupported"),
+        }
+    }
+}
+
+impl TryFrom<&str> for ErrorKind {
+    type Error = FolgoreError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "network" => Ok(Self::Network),
+            "auth" => Ok(Self::Auth),
+            "not-found" => Ok(Self::NotFound),
+            "rate-limited" => Ok(Self::RateLimited),
+            "invalid-response" => Ok(Self::InvalidResponse),
+            "validation" => Ok(Self::Validation),
+            "unsupported" => Ok(Self::Unsupported),
+            _ => Err(FolgoreError::validation(format!(
+                "error kind `{value}` not supported"
+            ))),
+        }
+    }
+}
+
+/// Error returned by a folgore backend.
+pub struct FolgoreError {
+    kind: ErrorKind,
+    backend: Option<BackendKind>,
+    msg: String,
+    source: Option<Box<dyn StdError + Send + Sync>>,
+}
+
+impl FolgoreError {
+    pub fn new<M: fmt::Display>(kind: ErrorKind, msg: M) -> Self {
+        Self {
+            kind,
+            backend: None,
+            msg: format!("{msg}"),
+            source: None,
+        }
+    }
+
+    pub fn network<M: fmt::Display>(msg: M) -> Self {
+        Self::new(ErrorKind::Network, msg)
+    }
+
+    pub fn auth<M: fmt::Display>(msg: M) -> Self {
+        Self::new(ErrorKind::Auth, msg)
+    }
+
+    pub fn not_found<M: fmt::Display>(msg: M) -> Self {
+        Self::new(ErrorKind::NotFound, msg)
+    }
+
+    pub fn rate_limited<M: fmt::Display>(msg: M) -> Self {
+        Self::new(ErrorKind::RateLimited, msg)
+    }
+
+    pub fn invalid_response<M: fmt::Display>(msg: M) -> Self {
+        Self::new(ErrorKind::InvalidResponse, msg)
+    }
+
+    pub fn validation<M: fmt::Display>(msg: M) -> Self {
+        Self::new(ErrorKind::Validation, msg)
+    }
+
+    pub fn unsupported<M: fmt::Display>(msg: M) -> Self {
+        Self::new(ErrorKind::Unsupported, msg)
+    }
+
+    /// Set the backend that generated the error.
+    pub fn with_backend(mut self, backend: BackendKind) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Attach the underline error.
+    pub fn with_source<E: StdError + Send + Sync + 'static>(mut self, source: E) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn backend(&self) -> Option<BackendKind> {
+        self.backend
+    }
+}
+
+impl Transient for FolgoreError {
+    fn is_transient(&self) -> bool {
+        matches!(self.kind, ErrorKind::Network | ErrorKind::RateLimited)
+    }
+}
+
+impl fmt::Display for FolgoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl fmt::Debug for FolgoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FolgoreError")
+            .field("kind", &self.kind)
+            .field("backend", &self.backend)
+            .field("msg", &self.msg)
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+impl StdError for FolgoreError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source
+            .as_ref()
+            .map(|err| err.as_ref() as &(dyn StdError + 'static))
+    }
+}
+
+impl From<serde_json::Error> for FolgoreError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::invalid_response(&err).with_source(err)
+    }
+}
+
+impl From<FolgoreError> for PluginError {
+    fn from(err: FolgoreError) -> Self {
+        let data = json!({
+            "kind": err.kind.to_string(),
+            "backend": err.backend.map(|backend| backend.to_string()),
+        });
+        PluginError::new(err.kind.code(), &err.msg, Some(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plugin_error_mapping() {
+        let err =
+            FolgoreError::rate_limited("too many requests").with_backend(BackendKind::Esplora);
+        assert!(err.is_transient());
+        let err: PluginError = err.into();
+        assert_eq!(
+            serde_json::to_value(&err).ok(),
+            Some(json!({
+                "code": -5,
+                "message": "too many requests",
+                "data": { "kind": "rate-limited", "backend": "esplora" },
+            }))
+        );
+    }
+
+    #[test]
+    fn test_transient_kinds() {
+        assert!(FolgoreError::network("connection refused").is_transient());
+        assert!(!FolgoreError::auth("wrong password").is_transient());
+        assert!(!FolgoreError::validation("bad tx").is_transient());
+        assert!(!FolgoreError::unsupported("dev command").is_transient());
+    }
+}
@@ -0,0 +1,235 @@
+//! Deterministic chain used by the conformance suite
+//! and served by the mock backend.
+//!
+//! The blocks are valid consensus encoded blocks (without
+//! a valid proof of work) with a single coinbase transaction,
+//! so a backend that decodes the blocks can serve them too.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use bitcoin_hashes::hex::FromHex;
+use bitcoin_hashes::{sha256d, Hash};
+
+use crate::utils::ByteBuf;
+
+/// The amount of each coinbase output in sats.
+pub const COINBASE_AMOUNT: u64 = 50 * 100_000_000;
+/// The script of each coinbase output (`OP_TRUE`).
+pub const COINBASE_SCRIPT: &str = "51";
+/// The hash of the regtest genesis block.
+pub const REGTEST_GENESIS_HASH: &str =
+    "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206";
+/// The coinbase of the genesis block, the same for all the bitcoin networks.
+const GENESIS_COINBASE: &str = "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+/// The time of the regtest genesis block.
+const GENESIS_TIME: u32 = 1_296_688_602;
+
+/// A block of the fixture chain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FixtureBlock {
+    pub height: u64,
+    /// The block hash as a hexadecimal string.
+    pub hash: String,
+    /// The block content as a hexadecimal string.
+    pub raw: String,
+    /// The txid of the coinbase transaction.
+    pub coinbase_txid: String,
+    /// The coinbase transaction as a hexadecimal string.
+    pub coinbase: String,
+}
+
+/// A transaction output of the fixture chain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FixtureOutput {
+    pub txid: String,
+    pub vout: u64,
+    pub amount: u64,
+    /// The scriptPubKey as a hexadecimal string.
+    pub script: String,
+}
+
+/// The chain that the backend under test must serve.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fixture {
+    /// The core lightning network name (e.g: `regtest`).
+    pub network: String,
+    /// The chain name returned by `getchaininfo` (e.g: `regtest`).
+    pub chain: String,
+    /// The blocks of the chain, where the index is the height.
+    pub blocks: Vec<FixtureBlock>,
+    /// An output that is not spent.
+    pub unspent: FixtureOutput,
+    /// An output that the backend must report as spent.
+    pub spent: FixtureOutput,
+    /// A transaction that the backend must refuse to broadcast,
+    /// it spends an output that does not exist.
+    pub rejected_tx: String,
+}
+
+impl Fixture {
+    /// Build a regtest chain of `count` blocks (at least 3) on top
+    /// of the regtest genesis block, the coinbase of block 1 is
+    /// unspent and the one of block 2 is spent.
+    pub fn regtest(count: u64) -> Self {
+        let count = count.max(3);
+        let mut blocks = vec![regtest_genesis()];
+        for height in 1..count {
+            let prev = blocks.last().map(|block| block.hash.as_str());
+            blocks.push(coinbase_block(height, prev, 0));
+        }
+        let output = |block: &FixtureBlock| FixtureOutput {
+            txid: block.coinbase_txid.clone(),
+            vout: 0,
+            amount: COINBASE_AMOUNT,
+            script: COINBASE_SCRIPT.to_owned(),
+        };
+        Self {
+            network: "regtest".to_owned(),
+            chain: "regtest".to_owned(),
+            unspent: output(&blocks[1]),
+            spent: output(&blocks[2]),
+            blocks,
+            rejected_tx: hex(&transaction(&[0x11; 32], &[0x00], 1000, 0)),
+        }
+    }
+
+    /// The height of the last block.
+    pub fn tip(&self) -> u64 {
+        self.blocks.len() as u64 - 1
+    }
+
+    pub fn block(&self, height: u64) -> Option<&FixtureBlock> {
+        self.blocks.get(height as usize)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    format!("{:02x}", ByteBuf(bytes))
+}
+
+/// Decode an hash in the displayed (reversed) order.
+fn hash_bytes(hash: &str) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    for (idx, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hash[idx * 2..idx * 2 + 2], 16).unwrap_or_default();
+    }
+    bytes.reverse();
+    bytes
+}
+
+/// A transaction with a single input and a single `OP_TRUE` output.
+fn transaction(prev_txid: &[u8; 32], script_sig: &[u8], amount: u64, locktime: u32) -> Vec<u8> {
+    let mut tx = vec![];
+    tx.extend(1u32.to_le_bytes());
+    // input
+    tx.push(1);
+    tx.extend(prev_txid);
+    tx.extend(if prev_txid == &[0; 32] {
+        u32::MAX.to_le_bytes()
+    } else {
+        0u32.to_le_bytes()
+    });
+    tx.push(script_sig.len() as u8);
+    tx.extend(script_sig);
+    tx.extend(u32::MAX.to_le_bytes());
+    // output
+    tx.push(1);
+    tx.extend(amount.to_le_bytes());
+    tx.push(1);
+    tx.push(0x51);
+    tx.extend(locktime.to_le_bytes());
+    tx
+}
+
+fn header(prev: Option<&str>, merkle_root: sha256d::Hash, time: u32, nonce: u32) -> Vec<u8> {
+    let mut header = vec![];
+    header.extend(1u32.to_le_bytes());
+    header.extend(prev.map(hash_bytes).unwrap_or([0; 32]));
+    header.extend(merkle_root.to_byte_array());
+    header.extend(time.to_le_bytes());
+    header.extend(0x207fffffu32.to_le_bytes());
+    header.extend(nonce.to_le_bytes());
+    header
+}
+
+/// The regtest genesis block, so a backend that detects the
+/// network from the genesis hash sees a regtest chain.
+pub fn regtest_genesis() -> FixtureBlock {
+    let coinbase = Vec::<u8>::from_hex(GENESIS_COINBASE).unwrap_or_default();
+    let txid = sha256d::Hash::hash(&coinbase);
+    let header = header(None, txid, GENESIS_TIME, 2);
+    let hash = sha256d::Hash::hash(&header);
+    let mut raw = header;
+    raw.push(1);
+    raw.extend(&coinbase);
+    FixtureBlock {
+        height: 0,
+        hash: hash.to_string(),
+        raw: hex(&raw),
+        coinbase_txid: txid.to_string(),
+        coinbase: hex(&coinbase),
+    }
+}
+
+/// Build the block at `height` on top of `prev` with a single
+/// coinbase transaction, a different `nonce` produces a different
+/// block (and coinbase) at the same height, e.g: to build a fork.
+pub fn coinbase_block(height: u64, prev: Option<&str>, nonce: u32) -> FixtureBlock {
+    block(height, prev, nonce, &[])
+}
+
+/// Build a block like `coinbase_block` that also contains
+/// the consensus encoded transactions `txs`.
+pub fn block(height: u64, prev: Option<&str>, nonce: u32, txs: &[Vec<u8>]) -> FixtureBlock {
+    // push the height in the coinbase like bip34
+    let mut script_sig = vec![0x04];
+    script_sig.extend((height as u32).to_le_bytes());
+    // the locktime of the coinbase is not enforced, so it
+    // makes the txid unique across forks
+    let coinbase = transaction(&[0; 32], &script_sig, COINBASE_AMOUNT, nonce);
+    let txid = sha256d::Hash::hash(&coinbase);
+
+    let mut txids = vec![txid];
+    txids.extend(txs.iter().map(|tx| sha256d::Hash::hash(tx)));
+    let time = GENESIS_TIME + height as u32 * 600;
+    let header = header(prev, merkle_root(txids), time, nonce);
+    let hash = sha256d::Hash::hash(&header);
+
+    let mut raw = header;
+    raw.extend(varint(txs.len() as u64 + 1));
+    raw.extend(&coinbase);
+    for tx in txs {
+        raw.extend(tx);
+    }
+    FixtureBlock {
+        height,
+        hash: hash.to_string(),
+        raw: hex(&raw),
+        coinbase_txid: txid.to_string(),
+        coinbase: hex(&coinbase),
+    }
+}
+
+fn merkle_root(mut hashes: Vec<sha256d::Hash>) -> sha256d::Hash {
+    while hashes.len() > 1 {
+        hashes = hashes
+            .chunks(2)
+            .map(|pair| {
+                let left = pair[0].to_byte_array();
+                // the last hash is paired with itself
+                let right = pair.get(1).unwrap_or(&pair[0]).to_byte_array();
+                sha256d::Hash::hash(&[left, right].concat())
+            })
+            .collect();
+    }
+    hashes[0]
+}
+
+/// Encode a bitcoin compact size integer.
+pub fn varint(value: u64) -> Vec<u8> {
+    match value {
+        0..=0xfc => vec![value as u8],
+        0xfd..=0xffff => [vec![0xfd], (value as u16).to_le_bytes().to_vec()].concat(),
+        0x10000..=0xffff_ffff => [vec![0xfe], (value as u32).to_le_bytes().to_vec()].concat(),
+        _ => [vec![0xff], value.to_le_bytes().to_vec()].concat(),
+    }
+}
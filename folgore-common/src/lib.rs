@@ -1,12 +1,24 @@
 pub mod client;
+#[cfg(feature = "conformance")]
+pub mod conformance;
+pub mod errors;
+pub mod fixture;
+pub mod recovery;
 pub mod stragegy;
 
 pub mod utils {
     pub use bitcoin_hashes;
 
-    #[macro_export]
-    macro_rules! hex (($hex:expr) => (<Vec<u8> as bitcoin_hashes::hex::FromHex>::from_hex($hex).unwrap()));
-    pub use hex;
+    use bitcoin_hashes::hex::FromHex;
+
+    use crate::errors::FolgoreError;
+
+    /// Decode a hex string received from core lightning or
+    /// from a backend, e.g: a raw transaction.
+    pub fn from_hex(hex: &str) -> Result<Vec<u8>, FolgoreError> {
+        Vec::<u8>::from_hex(hex)
+            .map_err(|err| FolgoreError::validation(format!("invalid hex string: {err}")))
+    }
 
     pub struct ByteBuf<'a>(pub &'a [u8]);
 
@@ -35,4 +47,4 @@ pub mod cln {
     pub use clightningrpc_plugin as plugin;
 }
 
-pub type Result<T> = std::result::Result<T, clightningrpc_plugin::errors::PluginError>;
+pub type Result<T> = std::result::Result<T, errors::FolgoreError>;
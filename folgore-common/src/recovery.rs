@@ -0,0 +1,380 @@
+//! Recovery Algorithms for the backends
+//! instead to just return an error.
+//!
+//! The `TimeoutRetry` is the strategy used by the plugin and
+//! by the command line tool, configured with a `RetryConfig`.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::errors::FolgoreError;
+use crate::stragegy::{RecoveryStrategy, Transient};
+
+/// The kind of retry logic used by a backend.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RetryKind {
+    /// Fail at the first error.
+    None,
+    /// Retry waiting always the same delay.
+    Fixed,
+    /// Retry doubling the delay at each attempt, with jitter.
+    Exponential,
+}
+
+impl TryFrom<&str> for RetryKind {
+    type Error = FolgoreError;
+
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        match value {
+            "none" => Ok(Self::None),
+            "fixed" => Ok(Self::Fixed),
+            "exponential" => Ok(Self::Exponential),
+            _ => Err(FolgoreError::validation(format!(
+                "retry strategy `{value}` not supported"
+            ))),
+        }
+    }
+}
+
+/// Configuration of the retry logic.
+#[derive(Clone, Debug)]
+pub struct RetryConfig {
+    pub kind: RetryKind,
+    /// The delay before the first retry.
+    pub base_delay: Duration,
+    /// The maximum delay between two retries.
+    pub max_delay: Duration,
+    /// How many times the request is retried.
+    pub max_attempts: u8,
+    /// The maximum time spent retrying a single request.
+    pub deadline: Option<Duration>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            kind: RetryKind::Exponential,
+            base_delay: Duration::from_secs(60),
+            max_delay: Duration::from_secs(480),
+            max_attempts: 4,
+            deadline: None,
+        }
+    }
+}
+
+/// Retry state of a single call, so every call
+/// start from the configured base delay.
+struct Backoff<'a> {
+    config: &'a RetryConfig,
+    /// Delay before the next attempt, without jitter.
+    timeout: Duration,
+    attempts: u8,
+    start: Instant,
+}
+
+impl<'a> Backoff<'a> {
+    fn new(config: &'a RetryConfig) -> Self {
+        Self {
+            config,
+            timeout: config.base_delay,
+            attempts: 0,
+            start: Instant::now(),
+        }
+    }
+
+    /// Return the delay to wait before the next attempt,
+    /// or `None` if we should give up.
+    fn next_delay(&mut self) -> Option<Duration> {
+        if self.config.kind == RetryKind::None || self.attempts >= self.config.max_attempts {
+            return None;
+        }
+        let delay = match self.config.kind {
+            RetryKind::Exponential if !self.timeout.is_zero() => {
+                // equal jitter: wait at least half of the timeout
+                let half = self.timeout / 2;
+                let jitter = rand::thread_rng().gen_range(Duration::ZERO..=half);
+                half + jitter
+            }
+            _ => self.timeout,
+        };
+        if let Some(deadline) = self.config.deadline {
+            if self.start.elapsed() + delay > deadline {
+                return None;
+            }
+        }
+        self.attempts += 1;
+        if self.config.kind == RetryKind::Exponential {
+            self.timeout = (self.timeout * 2).min(self.config.max_delay);
+        }
+        Some(delay)
+    }
+}
+
+/// Timeout Retry is a simple strategy that retry the call
+/// for more times with a increasing timeout.
+///
+/// This is useful in case of HTTPs API services that block
+/// a client request due the too many request in a range of
+/// period.
+///
+/// Esplora implement something similar, and we work around
+/// with this strategy.
+///
+/// The strategy keeps only the configuration, the retry state
+/// lives inside the `apply` call, so the same instance can be
+/// shared between threads and between calls.
+pub struct TimeoutRetry {
+    pub(crate) config: RetryConfig,
+}
+
+impl TimeoutRetry {
+    pub fn new(duration: Option<Duration>) -> Self {
+        let mut config = RetryConfig::default();
+        if let Some(duration) = duration {
+            config.base_delay = duration;
+        }
+        Self::with_config(config)
+    }
+
+    pub fn with_config(config: RetryConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl Default for TimeoutRetry {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl RecoveryStrategy for TimeoutRetry {
+    fn apply<T, E, F>(&self, cb: F) -> Result<T, E>
+    where
+        E: Transient + fmt::Debug,
+        F: Fn() -> Result<T, E>,
+    {
+        let mut backoff = Backoff::new(&self.config);
+        let mut result = cb();
+        while let Err(err) = &result {
+            if !err.is_transient() {
+                log::debug!("permanent error, skipping the retry logic: `{:?}`", err);
+                break;
+            }
+            let Some(delay) = backoff.next_delay() else {
+                log::info!(
+                    "we try {} times the request but the error persist",
+                    backoff.attempts
+                );
+                log::debug!("Error during the recovery strategy: `{:?}`", err);
+                break;
+            };
+            log::info!(
+                "running into retry logic due a request failing. Time `{}` waiting `{}` ms",
+                backoff.attempts,
+                delay.as_millis()
+            );
+            std::thread::sleep(delay);
+            log::info!("Waiting timeout end");
+            result = cb();
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{Backoff, RecoveryStrategy, RetryConfig, RetryKind, TimeoutRetry, Transient};
+
+    /// Error used by the tests, `true` if transient.
+    #[derive(Debug)]
+    struct TestError(bool);
+
+    impl Transient for TestError {
+        fn is_transient(&self) -> bool {
+            self.0
+        }
+    }
+
+    fn config(kind: RetryKind) -> RetryConfig {
+        RetryConfig {
+            kind,
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(40),
+            max_attempts: 4,
+            deadline: None,
+        }
+    }
+
+    #[test]
+    fn test_simple_retry() {
+        let strategy = TimeoutRetry::new(Some(Duration::from_millis(10)));
+
+        let err: Result<(), TestError> = strategy.apply(|| Err(TestError(true)));
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_state_strategy_one() {
+        let strategy = TimeoutRetry::new(Some(Duration::from_millis(10)));
+
+        let calls = AtomicUsize::new(0);
+        let _: Result<(), TestError> = strategy.apply(|| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(TestError(true))
+        });
+        // the first call plus 4 retries
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn test_state_strategy_two() {
+        let strategy = TimeoutRetry::new(Some(Duration::from_millis(10)));
+
+        let calls = AtomicUsize::new(0);
+        let _: Result<(), TestError> = strategy.apply(|| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_repeated_calls_start_fresh() {
+        let strategy = TimeoutRetry::with_config(config(RetryKind::Exponential));
+
+        for _ in 0..3 {
+            let calls = AtomicUsize::new(0);
+            let err: Result<(), TestError> = strategy.apply(|| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(TestError(true))
+            });
+            assert!(err.is_err());
+            assert_eq!(calls.load(Ordering::SeqCst), 5);
+
+            // a failing call do not influence the next one
+            let calls = AtomicUsize::new(0);
+            let ok: Result<(), TestError> = strategy.apply(|| {
+                if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return Err(TestError(true));
+                }
+                Ok(())
+            });
+            assert!(ok.is_ok());
+            assert_eq!(calls.load(Ordering::SeqCst), 2);
+        }
+    }
+
+    #[test]
+    fn test_shared_between_threads() {
+        let strategy = Arc::new(TimeoutRetry::with_config(config(RetryKind::Fixed)));
+
+        let workers = (0..4)
+            .map(|_| {
+                let strategy = strategy.clone();
+                std::thread::spawn(move || {
+                    let calls = AtomicUsize::new(0);
+                    let _: Result<(), TestError> = strategy.apply(|| {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        Err(TestError(true))
+                    });
+                    calls.load(Ordering::SeqCst)
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            assert_eq!(worker.join().ok(), Some(5));
+        }
+    }
+
+    #[test]
+    fn test_exponential_backoff() {
+        let config = config(RetryKind::Exponential);
+        let mut backoff = Backoff::new(&config);
+        let mut timeouts = vec![];
+        while let Some(delay) = backoff.next_delay() {
+            // the jitter keep the delay between the half and the timeout
+            assert!(delay <= Duration::from_millis(40));
+            timeouts.push(backoff.timeout);
+        }
+        assert_eq!(
+            timeouts,
+            [20, 40, 40, 40].map(Duration::from_millis).to_vec()
+        );
+
+        // a new call start again from the base delay
+        let backoff = Backoff::new(&config);
+        assert_eq!(backoff.timeout, Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_no_retry() {
+        let strategy = TimeoutRetry::with_config(config(RetryKind::None));
+
+        let calls = AtomicUsize::new(0);
+        let err: Result<(), TestError> = strategy.apply(|| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(TestError(true))
+        });
+        assert!(err.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_fixed_retry() {
+        let config = RetryConfig {
+            max_attempts: 2,
+            ..config(RetryKind::Fixed)
+        };
+        let mut backoff = Backoff::new(&config);
+        assert_eq!(backoff.next_delay(), Some(Duration::from_millis(10)));
+        assert_eq!(backoff.next_delay(), Some(Duration::from_millis(10)));
+        assert_eq!(backoff.next_delay(), None);
+    }
+
+    #[test]
+    fn test_deadline() {
+        let strategy = TimeoutRetry::with_config(RetryConfig {
+            max_attempts: 100,
+            deadline: Some(Duration::from_millis(100)),
+            ..config(RetryKind::Exponential)
+        });
+
+        let calls = AtomicUsize::new(0);
+        let err: Result<(), TestError> = strategy.apply(|| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(TestError(true))
+        });
+        assert!(err.is_err());
+        // the deadline stops the retry logic before the max attempts
+        assert!(calls.load(Ordering::SeqCst) < 100);
+    }
+
+    #[test]
+    fn test_permanent_error_not_retried() {
+        let strategy = TimeoutRetry::with_config(config(RetryKind::Exponential));
+
+        let calls = AtomicUsize::new(0);
+        let err: Result<(), TestError> = strategy.apply(|| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(TestError(false))
+        });
+        assert!(err.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // a transient error followed by a permanent one stops the retry
+        let calls = AtomicUsize::new(0);
+        let err: Result<(), TestError> = strategy.apply(|| {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            Err(TestError(call == 0))
+        });
+        assert!(err.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -22,13 +22,28 @@
 //! strategy of recovery from an error. This will be used when
 //! a request fails and core lightning do not admit failure.
 //!
+//! Not all the errors are worth a retry, so the backend
+//! errors must tell if they are transient (e.g: timeouts,
+//! connection resets, rate limits) or permanent, and the
+//! strategy retries only the transient ones.
+//!
 //! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
-use crate::Result;
+use std::fmt;
+
+/// Classification of a backend error.
+pub trait Transient {
+    /// Return true if the error is temporary, and
+    /// the same request may succeed if retried.
+    fn is_transient(&self) -> bool;
+}
 
 pub trait RecoveryStrategy: Send + Sync {
     /// Apply the algorithm implemented by
-    /// the kind of recovery strategy,
-    fn apply<T, F>(&self, cb: F) -> Result<T>
+    /// the kind of recovery strategy, only the
+    /// transient errors are retried, a permanent
+    /// error is returned to the caller immediately.
+    fn apply<T, E, F>(&self, cb: F) -> Result<T, E>
     where
-        F: Fn() -> Result<T>;
+        E: Transient + fmt::Debug,
+        F: Fn() -> Result<T, E>;
 }
@@ -0,0 +1,207 @@
+//! Minimal HTTP client for the esplora REST API.
+//!
+//! We need access to the response headers (e.g: `Retry-After`)
+//! to play nice with the public esplora instances, so this
+//! is a small wrapper around `ureq`.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::fmt;
+use std::io::Read;
+use std::time::{Duration, SystemTime};
+
+use serde::de::DeserializeOwned;
+
+use folgore_common::client::BackendKind;
+use folgore_common::errors::{ErrorKind, FolgoreError};
+use folgore_common::prelude::json;
+
+/// Default timeout for a single esplora request.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Error returned by an esplora endpoint.
+#[derive(Debug, Clone)]
+pub struct EsploraError {
+    code: u64,
+    msg: String,
+    retry_after: Option<Duration>,
+}
+
+impl EsploraError {
+    pub fn new<C: TryInto<u64>, M: fmt::Display>(code: C, msg: M) -> Self {
+        Self {
+            code: code.try_into().unwrap_or_default(),
+            msg: format!("{msg}"),
+            retry_after: None,
+        }
+    }
+
+    /// HTTP status code of the response, `0` when the
+    /// request fails before receiving a response.
+    pub fn code(&self) -> u64 {
+        self.code
+    }
+
+    /// How long the server asked us to wait before
+    /// sending a new request (`Retry-After` header).
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    pub(crate) fn with_retry_after(mut self, retry_after: Option<Duration>) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Return true if the endpoint is not able to serve the
+    /// request right now, so the request can be moved to another
+    /// endpoint of the pool.
+    pub fn is_unavailable(&self) -> bool {
+        self.code == 429 || self.code >= 500 || self.code < 100
+    }
+}
+
+impl fmt::Display for EsploraError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.code == 0 {
+            write!(f, "{}", self.msg)
+        } else {
+            write!(f, "{} (http code {})", self.msg, self.code)
+        }
+    }
+}
+
+impl std::error::Error for EsploraError {}
+
+impl From<EsploraError> for FolgoreError {
+    fn from(err: EsploraError) -> Self {
+        let kind = match err.code {
+            401 | 403 => ErrorKind::Auth,
+            404 => ErrorKind::NotFound,
+            429 => ErrorKind::RateLimited,
+            400 => ErrorKind::Validation,
+            // we received an answer that we are not able to decode
+            200 => ErrorKind::InvalidResponse,
+            _ if err.is_unavailable() => ErrorKind::Network,
+            _ => ErrorKind::InvalidResponse,
+        };
+        FolgoreError::new(kind, &err)
+            .with_backend(BackendKind::Esplora)
+            .with_source(err)
+    }
+}
+
+/// Parse the value of the `Retry-After` header, that can be
+/// expressed in seconds or as an HTTP date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+pub struct EsploraClient {
+    agent: ureq::Agent,
+    url: String,
+}
+
+impl EsploraClient {
+    pub fn new(url: &str) -> Result<Self, EsploraError> {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(EsploraError::new(
+                0,
+                format!("esplora url `{url}` with an unsupported scheme"),
+            ));
+        }
+        let agent = ureq::AgentBuilder::new().timeout(DEFAULT_TIMEOUT).build();
+        Ok(Self {
+            agent,
+            url: url.trim_end_matches('/').to_owned(),
+        })
+    }
+
+    fn read(&self, response: Result<ureq::Response, ureq::Error>) -> Result<Vec<u8>, EsploraError> {
+        let response = match response {
+            Ok(response) => response,
+            Err(ureq::Error::Status(code, response)) => {
+                let retry_after = response.header("Retry-After").and_then(parse_retry_after);
+                let msg = response
+                    .into_string()
+                    .unwrap_or_else(|err| format!("{err}"));
+                return Err(EsploraError::new(code, msg).with_retry_after(retry_after));
+            }
+            Err(err) => return Err(EsploraError::new(0, err)),
+        };
+        let mut body = vec![];
+        response
+            .into_reader()
+            .read_to_end(&mut body)
+            .map_err(|err| EsploraError::new(0, err))?;
+        Ok(body)
+    }
+
+    pub fn raw_call(&self, path: &str) -> Result<Vec<u8>, EsploraError> {
+        let response = self.agent.get(&format!("{}{path}", self.url)).call();
+        self.read(response)
+    }
+
+    pub fn call<T: DeserializeOwned>(&self, path: &str) -> Result<T, EsploraError> {
+        let body = self.raw_call(path)?;
+        // the server answered, so this is not an availability problem.
+        json::from_slice(&body).map_err(|err| EsploraError::new(200, err))
+    }
+
+    pub fn raw_post(&self, path: &str, body: &[u8]) -> Result<Vec<u8>, EsploraError> {
+        let response = self
+            .agent
+            .post(&format!("{}{path}", self.url))
+            .send_bytes(body);
+        self.read(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use folgore_common::errors::{ErrorKind, FolgoreError};
+    use folgore_common::stragegy::Transient;
+
+    use super::{parse_retry_after, EsploraError};
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 3 "), Some(Duration::from_secs(3)));
+        // a date in the past means that we can retry now
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+        // the pause is capped by the rate limiter, not here
+        assert_eq!(
+            parse_retry_after("18446744073709551615"),
+            Some(Duration::from_secs(u64::MAX))
+        );
+        assert_eq!(parse_retry_after("18446744073709551616"), None);
+    }
+
+    #[test]
+    fn test_error_kind() {
+        let kind = |code: u64| FolgoreError::from(EsploraError::new(code, "")).kind();
+        assert_eq!(kind(0), ErrorKind::Network);
+        assert_eq!(kind(502), ErrorKind::Network);
+        assert_eq!(kind(429), ErrorKind::RateLimited);
+        assert_eq!(kind(404), ErrorKind::NotFound);
+        assert_eq!(kind(400), ErrorKind::Validation);
+        assert_eq!(kind(200), ErrorKind::InvalidResponse);
+
+        assert!(FolgoreError::from(EsploraError::new(503, "")).is_transient());
+        assert!(!FolgoreError::from(EsploraError::new(400, "")).is_transient());
+    }
+}
@@ -1,31 +1,49 @@
 #![deny(clippy::unwrap_used)]
-use std::collections::BTreeMap;
+mod http;
+pub mod parse;
+mod pool;
+mod ratelimit;
+
 use std::collections::HashMap;
 use std::fmt;
-use std::fmt::Display;
 use std::sync::Arc;
+use std::time::Duration;
 
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use serde_json::json;
-
-use esplora_api::EsploraAPI;
 
-use folgore_common::client::fee_estimator::{FeeEstimator, FeePriority, FEE_RATES};
-use folgore_common::client::FolgoreBackend;
-use folgore_common::cln;
-use folgore_common::cln::json_utils;
-use folgore_common::cln::plugin::error;
-use folgore_common::cln::plugin::errors::PluginError;
-use folgore_common::cln::plugin::types::LogLevel;
+use folgore_common::client::fee_estimator::FeeTargets;
+use folgore_common::client::{
+    BackendConfig, BackendKind, BackendOption, BackendRegistry, BroadcastResult, Capabilities,
+    ChainInfo, FeeEstimates, FolgoreBackend, RawBlock, UtxoOut,
+};
 use folgore_common::cln::rpc::LightningRPC;
+use folgore_common::errors::FolgoreError;
 use folgore_common::prelude::log;
 use folgore_common::stragegy::RecoveryStrategy;
 use folgore_common::utils::ByteBuf;
 
-#[derive(Clone)]
+pub use http::EsploraError;
+pub use pool::{EndpointHealth, EsploraPool};
+pub use ratelimit::{RateLimit, DEFAULT_MAX_PAUSE};
+
+/// Internals used by the fuzz targets, they are not
+/// part of the public API.
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing {
+    pub use crate::http::parse_retry_after;
+    pub use crate::ratelimit::TokenBucket;
+}
+
+#[derive(Clone, Debug, PartialEq)]
 enum Network {
     Bitcoin(String),
     Testnet(String),
+    Testnet4(String),
+    Signet(String),
+    /// Regtest is a local chain, so there is no
+    /// public esplora instance for it.
+    Regtest,
     #[allow(dead_code)]
     Liquid(String),
     BitcoinTor(String),
@@ -35,20 +53,25 @@ enum Network {
 }
 
 impl Network {
-    pub fn url(&self) -> String {
+    /// Return the url of the public esplora instance
+    /// for the network, if any.
+    pub fn url(&self) -> Option<String> {
         match &self {
-            Self::Bitcoin(url) => url.to_string(),
-            Self::Liquid(url) => url.to_string(),
-            Self::Testnet(url) => url.to_string(),
-            Self::BitcoinTor(url) => url.to_string(),
-            Self::TestnetTor(url) => url.to_string(),
-            Self::LiquidTor(url) => url.to_string(),
+            Self::Bitcoin(url) => Some(url.to_string()),
+            Self::Liquid(url) => Some(url.to_string()),
+            Self::Testnet(url) => Some(url.to_string()),
+            Self::Testnet4(url) => Some(url.to_string()),
+            Self::Signet(url) => Some(url.to_string()),
+            Self::BitcoinTor(url) => Some(url.to_string()),
+            Self::TestnetTor(url) => Some(url.to_string()),
+            Self::LiquidTor(url) => Some(url.to_string()),
+            Self::Regtest => None,
         }
     }
 }
 
 impl TryFrom<&str> for Network {
-    type Error = PluginError;
+    type Error = FolgoreError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         match value {
@@ -64,47 +87,63 @@ impl TryFrom<&str> for Network {
                 "http://explorerzydxu5ecjrkwceayqybizmpjjznk5izmitf2modhcusuqlid.onion/testnet/api"
                     .to_owned(),
             )),
-            "signet" => Ok(Self::Testnet("https://mempool.space/signet/api".to_owned())),
+            "testnet4" => Ok(Self::Testnet4(
+                "https://mempool.space/testnet4/api".to_owned(),
+            )),
+            "signet" => Ok(Self::Signet("https://mempool.space/signet/api".to_owned())),
+            "regtest" => Ok(Self::Regtest),
             "liquid" => Ok(Self::Liquid(
                 "https://blockstream.info/liquid/api".to_owned(),
             )),
-            _ => Err(error!("network {value} not supported")),
+            _ => Err(
+                FolgoreError::unsupported(format!("network {value} not supported"))
+                    .with_backend(BackendKind::Esplora),
+            ),
         }
     }
 }
 
-// FIXME: move this inside the Plugin API to map the error
-/// convert the error to a plugin error
-fn from<T: Display>(value: T) -> PluginError {
-    PluginError::new(-1, &format!("{value}"), None)
-}
-
 #[derive(Clone)]
 pub struct Esplora<R: RecoveryStrategy> {
-    client: Arc<EsploraAPI>,
+    client: Arc<EsploraPool>,
     recovery_strategy: Arc<R>,
     /// CLN RPC path
     cln_rpc_path: String,
+    fee_targets: FeeTargets,
 }
 
 impl<R: RecoveryStrategy> Esplora<R> {
+    /// Build a new esplora backend that use the list of `urls`,
+    /// if the list is empty the default url for the network is used.
+    ///
+    /// An url that answers with a `Retry-After` header is not
+    /// used for at most `max_retry_after`.
     pub fn new(
         network: &str,
-        url: Option<String>,
+        urls: Vec<String>,
+        rate_limit: Option<RateLimit>,
+        max_retry_after: Duration,
         strategy: Arc<R>,
         cln_path: &str,
-    ) -> Result<Self, PluginError> {
-        let url = if let Some(url) = url {
-            url
+    ) -> Result<Self, FolgoreError> {
+        let urls = if urls.is_empty() {
+            let url = Network::try_from(network)?.url().ok_or(FolgoreError::unsupported(format!(
+                "network `{network}` has no public esplora instance, please specify `bitcoin-esplora-url`"
+            )).with_backend(BackendKind::Esplora))?;
+            vec![url]
         } else {
-            let network = Network::try_from(network)?;
-            network.url()
+            urls
         };
-        let builder = EsploraAPI::new(&url).map_err(|err| error!("{err}"))?;
+        let pool = EsploraPool::new(&urls, rate_limit, max_retry_after).map_err(|err| {
+            FolgoreError::validation(&err)
+                .with_backend(BackendKind::Esplora)
+                .with_source(err)
+        })?;
         Ok(Self {
-            client: Arc::new(builder),
+            client: Arc::new(pool),
             recovery_strategy: strategy,
             cln_rpc_path: cln_path.to_string(),
+            fee_targets: FeeTargets::default(),
         })
     }
 
@@ -112,120 +151,165 @@ impl<R: RecoveryStrategy> Esplora<R> {
         &self,
         method: &str,
         payload: T,
-    ) -> Result<U, PluginError> {
+    ) -> Result<U, FolgoreError> {
         let rpc = LightningRPC::new(&self.cln_rpc_path);
-        let response: U = rpc.call(method, payload).map_err(|err| error!("{err}"))?;
+        let response: U = rpc
+            .call(method, payload)
+            .map_err(|err| FolgoreError::network(format!("core lightning `{method}`: {err}")))?;
         Ok(response)
     }
-}
 
-fn fee_in_range(estimation: &HashMap<String, f64>, from: u64, to: u64) -> Option<i64> {
-    for rate in from..to {
-        let key = &format!("{rate}");
-        if estimation.contains_key(key) {
-            // Esplora return sat/vByte but core lightnign wants sat/kvB
-            return Some(estimation[key] as i64 * 1000);
-        }
+    /// Build the esplora backend from the options
+    /// returned by `options`.
+    pub fn from_config(config: &BackendConfig, strategy: Arc<R>) -> Result<Self, FolgoreError> {
+        let urls = config
+            .get_str("bitcoin-esplora-url")
+            .map(|urls| {
+                urls.split(',')
+                    .map(|url| url.trim())
+                    .filter(|url| !url.is_empty())
+                    .map(|url| url.to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let rate_limit = match config.get_str("bitcoin-esplora-ratelimit") {
+            Some(rate) => {
+                let rate = match rate.parse::<f64>() {
+                    Ok(rate) if rate > 0.0 => rate,
+                    _ => {
+                        return Err(FolgoreError::validation(format!(
+                            "invalid `bitcoin-esplora-ratelimit` value `{rate}`"
+                        ))
+                        .with_backend(BackendKind::Esplora))
+                    }
+                };
+                let burst = config
+                    .get_u64("bitcoin-esplora-burst")?
+                    .map(u32::try_from)
+                    .transpose()
+                    .map_err(|err| {
+                        FolgoreError::validation(format!(
+                            "invalid `bitcoin-esplora-burst` value: {err}"
+                        ))
+                        .with_backend(BackendKind::Esplora)
+                    })?;
+                Some(RateLimit::new(rate, burst))
+            }
+            None => None,
+        };
+        let max_retry_after = config
+            .get_u64("bitcoin-esplora-max-retry-after")
+            .map_err(|err| err.with_backend(BackendKind::Esplora))?
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_MAX_PAUSE);
+        let fee_targets = FeeTargets::from_config(config)
+            .map_err(|err| err.with_backend(BackendKind::Esplora))?;
+        // FIXME: check if there is the proxy enabled to pass the tor addrs
+        Ok(Self::new(
+            &config.network,
+            urls,
+            rate_limit,
+            max_retry_after,
+            strategy,
+            &config.rpc_path,
+        )?
+        .with_fee_targets(fee_targets))
     }
-    log::info!(
-        "fee rate not found for target {from} in the range map {:?}",
-        estimation,
-    );
-    None
-}
 
-fn estimate_fees_from_source(
-    fee_rates: &HashMap<String, f64>,
-) -> Result<serde_json::Value, PluginError> {
-    let mut fee_map = BTreeMap::new();
-    // FIXME: missing the mempool min fee, we should make a better soltution here
-    let fee =
-        fee_in_range(fee_rates, 100, 150).expect("mempool minimum fee range not able to calculate");
-    fee_map.insert(0, fee as u64);
-    for FeePriority(block, _) in FEE_RATES.iter().cloned() {
-        let diff = block as u64;
-        // Take a good range of blocks because esplora estimate bloks really bad
-        // but with this + 20% we should be enough good to get what we want
-        let range = (block as f64 * 0.50).round() as u64;
-        let Some(fee) = fee_in_range(fee_rates, block.into(), (block + range as u16).into()) else {
-            continue;
-        };
-        fee_map.insert(diff, fee as u64);
+    /// Estimate the fees of the `targets` instead of the default ones.
+    pub fn with_fee_targets(mut self, targets: FeeTargets) -> Self {
+        self.fee_targets = targets;
+        self
     }
-    if fee_map.len() != FEE_RATES.len() + 1 {
-        return FeeEstimator::null_estimate_fees();
+
+    /// Return the health of the esplora endpoints used by this backend.
+    pub fn health(&self) -> Vec<EndpointHealth> {
+        self.client.health()
     }
-    let resp = FeeEstimator::build_estimate_fees(&fee_map)?;
-    Ok(resp)
 }
 
-fn raw_to_num(buff: &[u8]) -> i64 {
-    let buf = String::from_utf8(buff.to_vec()).expect("impossible convert the buff to a string");
-    buf.parse().expect("impossible parse a string into a i64")
+/// The options read by the esplora backend.
+pub fn options() -> Vec<BackendOption> {
+    vec![
+        BackendOption::string(
+            "bitcoin-esplora-url",
+            "A comma separated list of esplora backend urls where to fetch the bitcoin data",
+        ),
+        BackendOption::string(
+            "bitcoin-esplora-ratelimit",
+            "Maximum number of requests per second sent to each esplora url (by default unlimited)",
+        ),
+        BackendOption::int(
+            "bitcoin-esplora-burst",
+            "Number of requests that can be sent in a burst to each esplora url",
+        ),
+        BackendOption::int(
+            "bitcoin-esplora-max-retry-after",
+            "Maximum seconds an esplora url is not used when it answers with a `Retry-After` header (by default 60)",
+        ),
+        FeeTargets::option(),
+    ]
 }
 
-impl<T: Clone, S: RecoveryStrategy> FolgoreBackend<T> for Esplora<S> {
-    fn kind(&self) -> folgore_common::client::BackendKind {
-        folgore_common::client::BackendKind::Esplora
+/// Register the esplora backend with the name `esplora`.
+pub fn register<R: RecoveryStrategy + 'static>(
+    registry: &mut BackendRegistry<R>,
+) -> Result<(), FolgoreError> {
+    registry.register(
+        "esplora",
+        options(),
+        Box::new(|config, strategy| Ok(Arc::new(Esplora::from_config(config, strategy)?))),
+    )?;
+    Ok(())
+}
+
+impl<S: RecoveryStrategy> FolgoreBackend for Esplora<S> {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Esplora
     }
 
-    fn sync_block_by_height(
-        &self,
-        _: &mut cln::plugin::plugin::Plugin<T>,
-        height: u64,
-    ) -> Result<serde_json::Value, PluginError> {
-        let fail_resp = json!({
-            "blockhash": null,
-            "block": null,
-        });
+    fn capabilities(&self) -> Capabilities {
+        // esplora implements also the dev command
+        Capabilities::native()
+    }
 
-        let current_height = self
-            .recovery_strategy
-            .apply(|| {
-                self.client
-                    .raw_call("/blocks/tip/height")
-                    .map_err(|err| error!("{err}"))
-                    .map(|raw| raw_to_num(&raw))
-            })
-            .map_err(|err| error!("{err}"))?;
-        if height > current_height as u64 {
-            return Ok(fail_resp);
+    fn sync_block_by_height(&self, height: u64) -> Result<Option<RawBlock>, FolgoreError> {
+        let current_height = self.recovery_strategy.apply(|| {
+            self.client
+                .raw_call("/blocks/tip/height")
+                .map_err(FolgoreError::from)
+        })?;
+        let current_height = parse::height(&current_height)?;
+        if height > current_height {
+            return Ok(None);
         }
         // Now that we are sure that the block exist we can requesting it
         let block_hash = self.recovery_strategy.apply(|| {
             self.client
                 .raw_call(&format!("/block-height/{height}"))
-                .map_err(|err| error!("{err}"))
-                .and_then(|raw| String::from_utf8(raw).map_err(|err| error!("{err}")))
+                .map_err(FolgoreError::from)
         })?;
+        let block_hash = parse::block_hash(block_hash)?;
 
         let block = self.recovery_strategy.apply(|| {
             self.client
                 .raw_call(&format!("/block/{block_hash}/raw"))
-                .map_err(from)
+                .map_err(FolgoreError::from)
         })?;
 
-        let mut response = json_utils::init_payload();
-        json_utils::add_str(&mut response, "blockhash", &block_hash);
-        let bytes = ByteBuf(&block);
-        json_utils::add_str(&mut response, "block", &format!("{:02x}", bytes));
-        Ok(response)
+        Ok(Some(RawBlock {
+            hash: block_hash,
+            block: format!("{:02x}", ByteBuf(&block)),
+        }))
     }
 
-    fn sync_chain_info(
-        &self,
-        _: &mut cln::plugin::plugin::Plugin<T>,
-        _: Option<u64>,
-    ) -> Result<serde_json::Value, PluginError> {
-        let current_height = self
-            .recovery_strategy
-            .apply(|| {
-                self.client
-                    .raw_call("/blocks/tip/height")
-                    .map_err(|err| error!("{err}"))
-                    .map(|raw| raw_to_num(&raw))
-            })
-            .map_err(|err| error!("{err}"))?;
+    fn sync_chain_info(&self, _: Option<u64>) -> Result<ChainInfo, FolgoreError> {
+        let current_height = self.recovery_strategy.apply(|| {
+            self.client
+                .raw_call("/blocks/tip/height")
+                .map_err(FolgoreError::from)
+        })?;
+        let current_height = parse::height(&current_height)?;
 
         log::info!("blockchain height: {current_height}");
 
@@ -233,114 +317,93 @@ impl<T: Clone, S: RecoveryStrategy> FolgoreBackend<T> for Esplora<S> {
         let genesis = self.recovery_strategy.apply(|| {
             self.client
                 .raw_call("/block-height/0")
-                .map_err(|err| error!("{err}"))
-                .and_then(|raw| String::from_utf8(raw).map_err(|err| error!("{err}")))
+                .map_err(FolgoreError::from)
         })?;
+        let genesis = parse::block_hash(genesis)?;
 
         let network = match genesis.as_str() {
             "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f" => "main",
             "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943" => "test",
+            "00000000da84f2bafbbc53dee25a72ae507ff4914b867c565be350b0da8bf043" => "testnet4",
             "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6" => "signet",
+            "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206" => "regtest",
             "1466275836220db2944ca059a3a10ef6fd2ea684b0688d2c379296888a206003" => "liquidv1",
-            _ => return Err(error!("wrong chain hash {}", genesis)),
+            _ => {
+                return Err(
+                    FolgoreError::invalid_response(format!("wrong chain hash {}", genesis))
+                        .with_backend(BackendKind::Esplora),
+                )
+            }
         };
 
-        let mut response = json_utils::init_payload();
-        json_utils::add_str(&mut response, "chain", network);
-        json_utils::add_number(&mut response, "headercount", current_height);
-        json_utils::add_number(&mut response, "blockcount", current_height);
-        json_utils::add_bool(&mut response, "ibd", false);
-        Ok(response)
+        Ok(ChainInfo {
+            chain: network.to_owned(),
+            header_count: current_height,
+            block_count: current_height,
+            ibd: false,
+        })
     }
 
-    fn sync_estimate_fees(
-        &self,
-        _: &mut cln::plugin::plugin::Plugin<T>,
-    ) -> Result<serde_json::Value, PluginError> {
+    fn sync_estimate_fees(&self) -> Result<FeeEstimates, FolgoreError> {
         let fee_rates = self.recovery_strategy.apply(|| {
             self.client
                 .call::<HashMap<String, f64>>("/fee-estimates")
-                .map_err(from)
+                .map_err(FolgoreError::from)
         })?;
-        let resp = estimate_fees_from_source(&fee_rates)?;
-        Ok(resp)
+        parse::fee_estimates(&fee_rates, &self.fee_targets)
     }
 
-    fn sync_get_utxo(
-        &self,
-        plugin: &mut cln::plugin::plugin::Plugin<T>,
-        txid: &str,
-        idx: u64,
-    ) -> Result<serde_json::Value, PluginError> {
-        #[derive(Deserialize)]
-        struct TxOut {
-            value: u64,
-            scriptpubkey: String,
-        }
-
-        #[derive(Deserialize)]
-        struct Tx {
-            vout: Vec<TxOut>,
-        }
-
+    fn sync_get_utxo(&self, txid: &str, idx: u64) -> Result<Option<UtxoOut>, FolgoreError> {
         let txid = txid.to_string();
         let utxo = self.recovery_strategy.apply(|| {
-            let result = self.client.call::<Option<Tx>>(&format!("/tx/{txid}"));
+            let result = self
+                .client
+                .call::<Option<parse::Tx>>(&format!("/tx/{txid}"));
             if let Err(err) = result {
                 log::debug!("call to `tx/{txid}` API return error: {:?}", err);
                 let err_code = err.code();
                 if err_code == 404 {
                     return Ok(None);
                 } else if err_code == 400 {
-                    plugin.log(
-                        LogLevel::Warn,
-                        &format!("error from esplora API `{:?}`", err),
-                    );
+                    log::warn!("error from esplora API `{:?}`", err);
                     return Ok(None);
                 } else {
-                    return Err(error!("{err}"));
+                    return Err(FolgoreError::from(err));
                 }
             }
-            // SAFETY: Checking before so we should be safe to unwrap here
-            #[allow(clippy::unwrap_used)]
-            Ok(result.unwrap())
+            result.map_err(FolgoreError::from)
         })?;
 
-        let mut resp = json_utils::init_payload();
-        let Some(utxo) = utxo else {
-            return Ok(json!({
-                "amount": null,
-                "script": null,
-            }));
+        let Some(output) = utxo.and_then(|utxo| utxo.output(idx)) else {
+            return Ok(None);
         };
 
-        let output = &utxo.vout[idx as usize];
-        json_utils::add_number(&mut resp, "amount", output.value.try_into().map_err(from)?);
-        json_utils::add_str(&mut resp, "script", &output.scriptpubkey);
-        Ok(resp)
+        // the transaction is returned also if the output is spent
+        let outspend = self.recovery_strategy.apply(|| {
+            self.client
+                .call::<parse::Outspend>(&format!("/tx/{txid}/outspend/{idx}"))
+                .map_err(FolgoreError::from)
+        })?;
+        if outspend.spent {
+            return Ok(None);
+        }
+        Ok(Some(output))
     }
 
     fn sync_send_raw_transaction(
         &self,
-        _: &mut cln::plugin::plugin::Plugin<T>,
         tx: &str,
         _with_hight_fee: bool,
-    ) -> Result<serde_json::Value, PluginError> {
+    ) -> Result<BroadcastResult, FolgoreError> {
         let tx_send = self.client.raw_post("/tx", tx.as_bytes());
         log::info!("{:?}", tx_send.as_ref().map(|b| String::from_utf8_lossy(b)));
-        let mut resp = json_utils::init_payload();
-        json_utils::add_bool(&mut resp, "success", tx_send.is_ok());
-        if let Err(err) = tx_send {
-            json_utils::add_str(&mut resp, "errmsg", &err.to_string());
+        match tx_send {
+            Ok(_) => Ok(BroadcastResult::success()),
+            Err(err) => Ok(BroadcastResult::failure(err)),
         }
-        Ok(resp)
     }
 
-    fn sync_dev_updateutxo(
-        &self,
-        _plugin: &mut cln::plugin::plugin::Plugin<T>,
-        iamsure: bool,
-    ) -> Result<serde_json::Value, PluginError> {
+    fn sync_dev_updateutxo(&self, iamsure: bool) -> Result<serde_json::Value, FolgoreError> {
         log::info!("calling `sync_dev_updateutxo`");
         #[derive(Deserialize, Debug, Clone)]
         struct ListFunds {
@@ -356,33 +419,19 @@ impl<T: Clone, S: RecoveryStrategy> FolgoreBackend<T> for Esplora<S> {
         let outputs: ListFunds = self.call("listfunds", serde_json::json!({}))?;
         let mut changed = vec![];
         for output in outputs.outputs {
-            #[derive(Deserialize, Serialize, Debug, Clone)]
-            struct Outspend {
-                spent: bool,
-                status: Option<serde_json::Value>,
-            }
-            let outspend: Outspend = self
+            let outspend: parse::Outspend = self
                 .client
                 .call(&format!("/tx/{}/outspend/{}", output.txid, output.output))
-                .map_err(|err| error!("{err}"))?;
+                .map_err(FolgoreError::from)?;
             log::debug!("{:?}", outspend);
 
             // if it is not spend the user should use dev-rescan-outputs
             if !outspend.spent {
                 continue;
             }
-
-            let spentheight = outspend
-                .clone()
-                .status
-                .ok_or(error!("status object not found `{:?}`", outspend))?;
-            #[allow(clippy::unwrap_used)]
-            let confirmed = spentheight.get("confirmed").unwrap().as_bool().unwrap();
-            if !confirmed {
+            let Some(spentheight) = outspend.spent_height()? else {
                 continue;
-            }
-            #[allow(clippy::unwrap_used)]
-            let spentheight = spentheight.get("block_height").unwrap().as_i64().unwrap();
+            };
             let _: serde_json::Value = self.call(
                 "dev-updateutxo",
                 serde_json::json!({
@@ -395,14 +444,186 @@ impl<T: Clone, S: RecoveryStrategy> FolgoreBackend<T> for Esplora<S> {
             )?;
             changed.push(outspend);
         }
-        serde_json::to_value(&changed).map_err(|err| error!("{err}"))
+        Ok(serde_json::to_value(&changed)?)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use folgore_common::conformance::{self, Fixture};
+    use folgore_common::errors::ErrorKind;
+    use folgore_common::stragegy::Transient;
+    use folgore_mock::esplora::EsploraServer;
+    use folgore_mock::{MockBackend, NoRetry, OutPoint};
+
     use super::*;
 
+    fn esplora(server: &EsploraServer) -> Esplora<NoRetry> {
+        let Ok(esplora) = Esplora::new(
+            "regtest",
+            vec![server.url()],
+            None,
+            DEFAULT_MAX_PAUSE,
+            Arc::new(NoRetry),
+            "lightning-rpc",
+        ) else {
+            panic!("impossible build the esplora client");
+        };
+        esplora
+    }
+
+    fn server(mock: &Arc<MockBackend>) -> EsploraServer {
+        let Ok(server) = EsploraServer::start(mock.clone()) else {
+            panic!("impossible start the esplora server");
+        };
+        server
+    }
+
+    #[test]
+    fn test_network_mapping() {
+        let network = Network::try_from("signet").ok();
+        assert_eq!(
+            network,
+            Some(Network::Signet(
+                "https://mempool.space/signet/api".to_owned()
+            ))
+        );
+        let network = Network::try_from("testnet4").ok();
+        assert_eq!(
+            network.and_then(|network| network.url()),
+            Some("https://mempool.space/testnet4/api".to_owned())
+        );
+        let network = Network::try_from("regtest").ok();
+        assert_eq!(network, Some(Network::Regtest));
+        assert_eq!(network.and_then(|network| network.url()), None);
+    }
+
+    #[test]
+    fn test_conformance() {
+        let fixture = Fixture::regtest(10);
+        let mock = Arc::new(MockBackend::from_fixture(&fixture));
+        let server = server(&mock);
+        conformance::assert_conformance(&esplora(&server), &fixture);
+    }
+
+    #[test]
+    fn test_http_backend() {
+        let mock = Arc::new(MockBackend::new(5));
+        let server = server(&mock);
+        let esplora = esplora(&server);
+
+        let Ok(info) = esplora.sync_chain_info(None) else {
+            panic!("chain info not returned");
+        };
+        assert_eq!(info.chain, "regtest");
+        assert_eq!(info.block_count, 4);
+
+        let Ok(Some(block)) = esplora.sync_block_by_height(4) else {
+            panic!("block 4 not found");
+        };
+        assert_eq!(mock.sync_block_by_height(4).ok().flatten(), Some(block));
+        assert!(matches!(esplora.sync_block_by_height(5), Ok(None)));
+
+        let Ok(fees) = esplora.sync_estimate_fees() else {
+            panic!("fees not returned");
+        };
+        let Ok(expected) = mock.sync_estimate_fees() else {
+            panic!("fees not returned");
+        };
+        assert_eq!(fees.feerates, expected.feerates);
+        // esplora has no mempool minimum fee, so the floor is the slow fee
+        assert_eq!(fees.floor, expected.feerates[3].feerate);
+
+        let output = mock.create_output(10_000, "0014aa");
+        let Ok(Some(utxo)) = esplora.sync_get_utxo(&output.txid, 0) else {
+            panic!("utxo not found");
+        };
+        assert_eq!(utxo.amount, 10_000);
+        assert_eq!(utxo.script, "0014aa");
+        assert!(mock.spend(OutPoint::from(&output)));
+        assert!(matches!(esplora.sync_get_utxo(&output.txid, 0), Ok(None)));
+        assert!(matches!(
+            esplora.sync_get_utxo(&"00".repeat(32), 0),
+            Ok(None)
+        ));
+
+        let Ok(result) = esplora.sync_send_raw_transaction("0200", false) else {
+            panic!("broadcast failed");
+        };
+        assert!(!result.success);
+        assert!(result
+            .errmsg
+            .is_some_and(|errmsg| errmsg.contains("TX decode failed")));
+        assert!(server.requests().contains(&"POST /tx".to_owned()));
+    }
+
+    #[test]
+    fn test_http_errors() {
+        let mock = Arc::new(MockBackend::new(5));
+        let server = server(&mock);
+        let esplora = esplora(&server);
+
+        let kind = |result: Result<ChainInfo, FolgoreError>| result.err().map(|err| err.kind());
+        server.fail("/blocks/tip/height", 503, 1);
+        assert_eq!(
+            kind(esplora.sync_chain_info(None)),
+            Some(ErrorKind::Network)
+        );
+        server.fail("/blocks/tip/height", 429, 1);
+        assert_eq!(
+            kind(esplora.sync_chain_info(None)),
+            Some(ErrorKind::RateLimited)
+        );
+        server.fail("/block-height/0", 404, 1);
+        assert_eq!(
+            kind(esplora.sync_chain_info(None)),
+            Some(ErrorKind::NotFound)
+        );
+        assert!(esplora.sync_chain_info(None).is_ok());
+
+        // the server was rate limited
+        let health = esplora.health();
+        assert_eq!(health[0].failures, 2);
+        assert!(health[0].is_healthy());
+
+        server.fail("/fee-estimates", 500, 1);
+        let Err(err) = esplora.sync_estimate_fees() else {
+            panic!("the error is not returned");
+        };
+        assert!(err.is_transient());
+        assert_eq!(err.backend(), Some(BackendKind::Esplora));
+    }
+
+    #[test]
+    fn test_oversized_retry_after() {
+        let mock = Arc::new(MockBackend::new(5));
+        let server = server(&mock);
+        let Ok(esplora) = Esplora::new(
+            "regtest",
+            vec![server.url()],
+            None,
+            Duration::from_millis(200),
+            Arc::new(NoRetry),
+            "lightning-rpc",
+        ) else {
+            panic!("impossible build the esplora client");
+        };
+
+        server.retry_after("18446744073709551615");
+        server.fail("/blocks/tip/height", 429, 1);
+        let kind = |result: Result<ChainInfo, FolgoreError>| result.err().map(|err| err.kind());
+        assert_eq!(
+            kind(esplora.sync_chain_info(None)),
+            Some(ErrorKind::RateLimited)
+        );
+        // the url is paused only for `max_retry_after`
+        let start = std::time::Instant::now();
+        assert!(esplora.sync_chain_info(None).is_ok());
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(150), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+    }
+
     #[allow(clippy::unwrap_used)]
     #[test]
     fn test_estimatefees() {
@@ -437,20 +658,7 @@ mod tests {
           "22": 21.062
         });
         let fee_ranges: HashMap<String, f64> = serde_json::from_value(input).unwrap();
-        let fee_estimation = super::estimate_fees_from_source(&fee_ranges).unwrap();
-
-        #[derive(Deserialize, Debug)]
-        struct FeeEstimation {
-            feerates: Vec<PerBlockFee>,
-        }
-
-        #[derive(Deserialize, Debug)]
-        struct PerBlockFee {
-            blocks: u64,
-            feerate: u64,
-        }
-
-        let fee_estimation: FeeEstimation = serde_json::from_value(fee_estimation).unwrap();
+        let fee_estimation = parse::fee_estimates(&fee_ranges, &FeeTargets::default()).unwrap();
         assert!(!fee_estimation.feerates.is_empty(), "{:?}", fee_ranges);
         assert_eq!(fee_estimation.feerates[0].blocks, 2);
         assert_eq!(fee_estimation.feerates[1].blocks, 6);
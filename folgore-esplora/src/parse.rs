@@ -0,0 +1,222 @@
+//! Parsers of the esplora responses.
+//!
+//! The responses come from a server that we do not control,
+//! so the parsers return an error instead of panicking on
+//! an unexpected body. They are public to be fuzzed.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use folgore_common::client::fee_estimator::{FeeEstimator, FeePriority, FeeTargets};
+use folgore_common::client::{BackendKind, FeeEstimates, UtxoOut};
+use folgore_common::errors::FolgoreError;
+use folgore_common::prelude::log;
+
+fn invalid_response<T: std::fmt::Display>(msg: T) -> FolgoreError {
+    FolgoreError::invalid_response(msg).with_backend(BackendKind::Esplora)
+}
+
+/// Parse the body of `GET /blocks/tip/height`.
+pub fn height(body: &[u8]) -> Result<u64, FolgoreError> {
+    let body = std::str::from_utf8(body).map_err(invalid_response)?;
+    body.trim()
+        .parse()
+        .map_err(|err| invalid_response(format!("invalid height `{body}`: {err}")))
+}
+
+/// Parse the body of `GET /block-height/:height`.
+pub fn block_hash(body: Vec<u8>) -> Result<String, FolgoreError> {
+    String::from_utf8(body).map_err(|err| invalid_response(&err).with_source(err))
+}
+
+/// The transaction returned by `GET /tx/:txid`.
+#[derive(Deserialize, Debug)]
+pub struct Tx {
+    vout: Vec<TxOut>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TxOut {
+    value: u64,
+    scriptpubkey: String,
+}
+
+impl Tx {
+    /// The output at `idx`, `None` if the transaction
+    /// does not have it.
+    pub fn output(&self, idx: u64) -> Option<UtxoOut> {
+        let output = self.vout.get(usize::try_from(idx).ok()?)?;
+        Some(UtxoOut {
+            amount: output.value,
+            script: output.scriptpubkey.clone(),
+        })
+    }
+}
+
+/// The spending status of an output returned by
+/// `GET /tx/:txid/outspend/:vout`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Outspend {
+    pub spent: bool,
+    pub status: Option<Value>,
+}
+
+impl Outspend {
+    /// The height of the block that confirms the spending
+    /// transaction, `None` if it is not confirmed yet.
+    pub fn spent_height(&self) -> Result<Option<u64>, FolgoreError> {
+        let status = self.status.as_ref().ok_or(invalid_response(format!(
+            "status object not found `{self:?}`"
+        )))?;
+        let confirmed =
+            status
+                .get("confirmed")
+                .and_then(Value::as_bool)
+                .ok_or(invalid_response(format!(
+                    "invalid outspend status `{status}`"
+                )))?;
+        if !confirmed {
+            return Ok(None);
+        }
+        let height = status
+            .get("block_height")
+            .and_then(Value::as_u64)
+            .ok_or(invalid_response(format!(
+                "invalid outspend status `{status}`"
+            )))?;
+        Ok(Some(height))
+    }
+}
+
+/// Esplora does not estimate every block target, so the estimate
+/// of a target is the first one of the next 50% blocks, e.g: the
+/// estimate of 100 blocks is the first one between 100 and 149.
+const TARGET_TOLERANCE: f64 = 0.50;
+
+/// The first fee rate of the blocks in `from..to`.
+fn fee_in_range(estimation: &HashMap<String, f64>, from: u64, to: u64) -> Option<u64> {
+    for rate in from..to {
+        let Some(fee) = estimation.get(&format!("{rate}")) else {
+            continue;
+        };
+        // a negative or a not finite feerate is a bug of the server
+        if !fee.is_finite() || *fee < 0.0 {
+            log::warn!("invalid fee rate `{fee}` for target {rate}");
+            return None;
+        }
+        // Esplora return sat/vByte but core lightnign wants sat/kvB
+        return Some((*fee as u64).saturating_mul(1000));
+    }
+    log::info!(
+        "fee rate not found for target {from} in the range map {:?}",
+        estimation,
+    );
+    None
+}
+
+/// Build the estimates of the `targets` from the body of `GET /fee-estimates`,
+/// the estimates are null if one of the targets is missing. Esplora
+/// has a single estimate mode, so the mode of the targets is ignored.
+pub fn fee_estimates(
+    fee_rates: &HashMap<String, f64>,
+    targets: &FeeTargets,
+) -> Result<FeeEstimates, FolgoreError> {
+    let mut fee_map = BTreeMap::new();
+    // FIXME: missing the mempool min fee, we should make a better soltution here
+    let Some(fee) = fee_in_range(fee_rates, 100, 150) else {
+        return FeeEstimator::null_estimate_fees();
+    };
+    fee_map.insert(0, fee);
+    for FeePriority(block, _) in targets.iter() {
+        let diff = *block as u64;
+        let range = (*block as f64 * TARGET_TOLERANCE).round() as u64;
+        let Some(fee) = fee_in_range(fee_rates, diff, diff + range) else {
+            continue;
+        };
+        fee_map.insert(diff, fee);
+    }
+    FeeEstimator::build_estimate_fees(targets, &fee_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use folgore_common::errors::ErrorKind;
+
+    use super::*;
+
+    #[test]
+    fn test_invalid_responses() {
+        assert_eq!(height(b"42\n").ok(), Some(42));
+        for body in [&b""[..], b"-1", b"4a", b"\xff"] {
+            let Err(err) = height(body) else {
+                panic!("height `{body:?}` accepted");
+            };
+            assert_eq!(err.kind(), ErrorKind::InvalidResponse);
+        }
+
+        let Ok(tx) = serde_json::from_value::<Tx>(json!({
+            "vout": [{ "value": 1000, "scriptpubkey": "51" }],
+        })) else {
+            panic!("transaction not decoded");
+        };
+        assert!(tx.output(0).is_some());
+        assert!(tx.output(1).is_none());
+        assert!(tx.output(u64::MAX).is_none());
+
+        let outspend = |status: Value| Outspend {
+            spent: true,
+            status: Some(status),
+        };
+        let height = outspend(json!({ "confirmed": true, "block_height": 10 })).spent_height();
+        assert_eq!(height.ok(), Some(Some(10)));
+        let height = outspend(json!({ "confirmed": false })).spent_height();
+        assert_eq!(height.ok(), Some(None));
+        assert!(outspend(json!({ "confirmed": true }))
+            .spent_height()
+            .is_err());
+        assert!(outspend(json!([])).spent_height().is_err());
+
+        // without the floor the estimates are null
+        let fees = HashMap::from([("2".to_owned(), 10.0), ("6".to_owned(), -1.0)]);
+        let Ok(fees) = fee_estimates(&fees, &FeeTargets::default()) else {
+            panic!("fees not returned");
+        };
+        assert!(fees.feerates.is_empty());
+    }
+
+    #[test]
+    fn test_fee_targets_tolerance() {
+        let Ok(targets) = FeeTargets::parse("3,10,30") else {
+            panic!("invalid fee targets");
+        };
+        let mut fee_rates = HashMap::from([
+            ("4".to_owned(), 30.0),
+            ("14".to_owned(), 20.0),
+            ("44".to_owned(), 10.0),
+            ("100".to_owned(), 1.0),
+        ]);
+        let Ok(fees) = fee_estimates(&fee_rates, &targets) else {
+            panic!("fees not returned");
+        };
+        let feerates = fees
+            .feerates
+            .iter()
+            .map(|fee| (fee.blocks, fee.feerate))
+            .collect::<Vec<_>>();
+        assert_eq!(feerates, vec![(3, 30_000), (10, 20_000), (30, 10_000)]);
+        assert_eq!(fees.floor, 1000);
+
+        // 45 blocks are more than 50% over the target of 30 blocks
+        fee_rates.remove("44");
+        fee_rates.insert("45".to_owned(), 10.0);
+        let Ok(fees) = fee_estimates(&fee_rates, &targets) else {
+            panic!("fees not returned");
+        };
+        assert!(fees.feerates.is_empty());
+    }
+}
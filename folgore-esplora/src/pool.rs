@@ -0,0 +1,326 @@
+//! Pool of esplora endpoints used by a single backend.
+//!
+//! The pool keep using the same endpoint until it stops
+//! answering (e.g: rate limited, 5xx or connection errors),
+//! in that case the request is moved to the next endpoint
+//! without waiting, and the health of each endpoint is
+//! tracked to prefer the ones that are working.
+//!
+//! An endpoint that is paused by a `Retry-After` header or that
+//! has no rate limit token is skipped, and when no endpoint is
+//! available the request waits for the first one that is.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use folgore_common::prelude::log;
+
+use crate::http::{EsploraClient, EsploraError};
+use crate::ratelimit::{RateLimit, TokenBucket};
+
+/// Health information about an esplora endpoint.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct EndpointHealth {
+    pub url: String,
+    pub successes: u64,
+    pub failures: u64,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+}
+
+impl EndpointHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.consecutive_failures == 0
+    }
+}
+
+pub(crate) struct Endpoint {
+    pub(crate) url: String,
+    pub(crate) client: EsploraClient,
+    limiter: TokenBucket,
+    health: Mutex<EndpointHealth>,
+}
+
+impl Endpoint {
+    fn new(
+        url: &str,
+        rate_limit: Option<RateLimit>,
+        max_pause: Duration,
+    ) -> Result<Self, EsploraError> {
+        let client = EsploraClient::new(url)?;
+        Ok(Self {
+            url: url.to_owned(),
+            client,
+            limiter: TokenBucket::new(rate_limit, max_pause),
+            health: Mutex::new(EndpointHealth {
+                url: url.to_owned(),
+                ..Default::default()
+            }),
+        })
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.health
+            .lock()
+            .map(|health| health.is_healthy())
+            .unwrap_or(false)
+    }
+
+    fn success(&self) {
+        if let Ok(mut health) = self.health.lock() {
+            health.successes += 1;
+            health.consecutive_failures = 0;
+        }
+    }
+
+    fn failure(&self, err: &EsploraError) {
+        if let Ok(mut health) = self.health.lock() {
+            health.failures += 1;
+            health.consecutive_failures += 1;
+            health.last_error = Some(err.to_string());
+        }
+    }
+}
+
+pub struct EsploraPool {
+    endpoints: Vec<Endpoint>,
+    /// Index of the endpoint currently in use.
+    current: AtomicUsize,
+}
+
+impl EsploraPool {
+    /// Build a new pool, the `rate_limit` is applied to each
+    /// endpoint, and an endpoint is paused by the `Retry-After`
+    /// header for at most `max_pause`.
+    pub fn new(
+        urls: &[String],
+        rate_limit: Option<RateLimit>,
+        max_pause: Duration,
+    ) -> Result<Self, EsploraError> {
+        if urls.is_empty() {
+            return Err(EsploraError::new(0, "no esplora url specified"));
+        }
+        let endpoints = urls
+            .iter()
+            .map(|url| Endpoint::new(url, rate_limit, max_pause))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            endpoints,
+            current: AtomicUsize::new(0),
+        })
+    }
+
+    /// Return the health of all the endpoints inside the pool.
+    pub fn health(&self) -> Vec<EndpointHealth> {
+        self.endpoints
+            .iter()
+            .filter_map(|endpoint| endpoint.health.lock().ok().map(|health| health.clone()))
+            .collect()
+    }
+
+    pub fn raw_call(&self, path: &str) -> Result<Vec<u8>, EsploraError> {
+        self.apply(|endpoint| endpoint.client.raw_call(path))
+    }
+
+    pub fn call<T: DeserializeOwned>(&self, path: &str) -> Result<T, EsploraError> {
+        self.apply(|endpoint| endpoint.client.call::<T>(path))
+    }
+
+    pub fn raw_post(&self, path: &str, body: &[u8]) -> Result<Vec<u8>, EsploraError> {
+        self.apply(|endpoint| endpoint.client.raw_post(path, body))
+    }
+
+    /// Order in which the endpoints are tried, starting from the one
+    /// currently in use and keeping the unhealthy ones as last resort.
+    fn candidates(&self) -> Vec<usize> {
+        let size = self.endpoints.len();
+        let start = self.current.load(Ordering::Relaxed) % size;
+        let mut order = (0..size)
+            .map(|idx| (start + idx) % size)
+            .collect::<Vec<_>>();
+        // the sort is stable, so the rotation order is preserved
+        order.sort_by_key(|idx| !self.endpoints[*idx].is_healthy());
+        order
+    }
+
+    pub(crate) fn apply<T, F>(&self, cb: F) -> Result<T, EsploraError>
+    where
+        F: Fn(&Endpoint) -> Result<T, EsploraError>,
+    {
+        loop {
+            match self.try_endpoints(&cb) {
+                Ok(result) => return result,
+                Err(wait) => {
+                    log::debug!(
+                        "all the esplora endpoints are rate limited, waiting {} ms",
+                        wait.as_millis()
+                    );
+                    std::thread::sleep(wait);
+                }
+            }
+        }
+    }
+
+    /// Try the endpoints that are not rate limited, when all of them
+    /// are rate limited return the shortest time to wait before one
+    /// of them is available again.
+    fn try_endpoints<T, F>(&self, cb: &F) -> Result<Result<T, EsploraError>, Duration>
+    where
+        F: Fn(&Endpoint) -> Result<T, EsploraError>,
+    {
+        let mut last_err = None;
+        let mut wait: Option<Duration> = None;
+        for idx in self.candidates() {
+            let endpoint = &self.endpoints[idx];
+            if let Some(endpoint_wait) = endpoint.limiter.reserve(Instant::now()) {
+                log::debug!(
+                    "esplora endpoint `{}` rate limited for {} ms, skipping it",
+                    endpoint.url,
+                    endpoint_wait.as_millis()
+                );
+                wait = Some(wait.map_or(endpoint_wait, |wait| wait.min(endpoint_wait)));
+                continue;
+            }
+            match cb(endpoint) {
+                Err(err) if err.is_unavailable() => {
+                    if let Some(retry_after) = err.retry_after() {
+                        endpoint.limiter.pause(retry_after);
+                    }
+                    log::warn!(
+                        "esplora endpoint `{}` unavailable: `{err}`, moving to the next one",
+                        endpoint.url
+                    );
+                    endpoint.failure(&err);
+                    last_err = Some(err);
+                }
+                result => {
+                    // the endpoint answered, so it is alive also if
+                    // the answer is an error.
+                    endpoint.success();
+                    self.current.store(idx, Ordering::Relaxed);
+                    return Ok(result);
+                }
+            }
+        }
+        match (last_err, wait) {
+            (Some(err), _) => Ok(Err(err)),
+            (None, Some(wait)) => Err(wait),
+            (None, None) => Ok(Err(EsploraError::new(0, "no esplora endpoint available"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ratelimit::DEFAULT_MAX_PAUSE;
+
+    fn pool() -> EsploraPool {
+        let urls = ["https://one.api", "https://two.api", "https://three.api"]
+            .iter()
+            .map(|url| url.to_string())
+            .collect::<Vec<_>>();
+        #[allow(clippy::unwrap_used)]
+        EsploraPool::new(&urls, None, DEFAULT_MAX_PAUSE).unwrap()
+    }
+
+    #[test]
+    fn test_failover_on_unavailable() {
+        let pool = pool();
+        let result = pool.apply(|endpoint| match endpoint.url.as_str() {
+            "https://one.api" => Err(EsploraError::new(429, "too many requests")),
+            "https://two.api" => Err(EsploraError::new(503, "service unavailable")),
+            url => Ok(url.to_owned()),
+        });
+        assert_eq!(result.ok(), Some("https://three.api".to_owned()));
+
+        let health = pool.health();
+        assert_eq!(health[0].consecutive_failures, 1);
+        assert_eq!(health[1].consecutive_failures, 1);
+        assert!(health[2].is_healthy());
+
+        // the next request start from the last endpoint that answered
+        let result = pool.apply(|endpoint| Ok::<_, EsploraError>(endpoint.url.clone()));
+        assert_eq!(result.ok(), Some("https://three.api".to_owned()));
+    }
+
+    #[test]
+    fn test_no_failover_on_client_error() {
+        let pool = pool();
+        let result: Result<(), _> = pool.apply(|_| Err(EsploraError::new(404, "not found")));
+        assert_eq!(result.err().map(|err| err.code()), Some(404));
+        assert!(pool.health().iter().all(|health| health.is_healthy()));
+        assert_eq!(pool.health()[0].successes, 1);
+        assert_eq!(pool.health()[1].successes, 0);
+    }
+
+    #[test]
+    fn test_unhealthy_endpoint_tried_last() {
+        let pool = pool();
+        let _ = pool.apply(|endpoint| match endpoint.url.as_str() {
+            "https://one.api" => Err(EsploraError::new(500, "internal error")),
+            _ => Ok(()),
+        });
+        // start from three.api, so one.api is the next in the rotation
+        // but it is unhealthy, so we should prefer two.api.
+        pool.current.store(2, Ordering::Relaxed);
+        let result = pool.apply(|endpoint| match endpoint.url.as_str() {
+            "https://three.api" => Err(EsploraError::new(500, "internal error")),
+            url => Ok(url.to_owned()),
+        });
+        assert_eq!(result.ok(), Some("https://two.api".to_owned()));
+    }
+
+    #[test]
+    fn test_all_endpoints_down() {
+        let pool = pool();
+        let result: Result<(), _> = pool.apply(|_| Err(EsploraError::new(502, "bad gateway")));
+        assert_eq!(result.err().map(|err| err.code()), Some(502));
+        assert!(pool.health().iter().all(|health| !health.is_healthy()));
+    }
+
+    #[test]
+    fn test_skip_rate_limited_endpoint() {
+        let pool = pool();
+        let err = EsploraError::new(429, "too many requests")
+            .with_retry_after(Some(Duration::from_secs(30)));
+        let result = pool.apply(|endpoint| match endpoint.url.as_str() {
+            "https://one.api" => Err(err.clone()),
+            url => Ok(url.to_owned()),
+        });
+        assert_eq!(result.ok(), Some("https://two.api".to_owned()));
+
+        // one.api is paused, so it is not called also if it is
+        // the next one in the rotation.
+        pool.current.store(0, Ordering::Relaxed);
+        let result = pool.apply(|endpoint| match endpoint.url.as_str() {
+            "https://one.api" => panic!("paused endpoint called"),
+            url => Ok(url.to_owned()),
+        });
+        assert_eq!(result.ok(), Some("https://two.api".to_owned()));
+    }
+
+    #[test]
+    fn test_all_endpoints_rate_limited() {
+        let urls = vec!["https://one.api".to_owned()];
+        let Ok(pool) = EsploraPool::new(
+            &urls,
+            Some(RateLimit::new(50.0, Some(2))),
+            DEFAULT_MAX_PAUSE,
+        ) else {
+            panic!("impossible build the pool");
+        };
+        // the calls over the burst wait for the next token
+        let start = Instant::now();
+        for _ in 0..6 {
+            assert!(pool.apply(|_| Ok::<_, EsploraError>(())).is_ok());
+        }
+        assert!(start.elapsed() >= Duration::from_millis(60));
+        assert_eq!(pool.health()[0].successes, 6);
+    }
+}
@@ -0,0 +1,143 @@
+//! Client side rate limiter for the esplora endpoints.
+//!
+//! Public esplora instances rate limit the clients, so
+//! instead of waiting to be throttled we limit the requests
+//! with a token bucket, and we stop sending requests when the
+//! server ask us to with a `Retry-After` header, for at most
+//! `max_pause` because the header is controlled by the server.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Maximum time an endpoint is paused by a `Retry-After` header.
+pub const DEFAULT_MAX_PAUSE: Duration = Duration::from_secs(60);
+
+/// Configuration of the rate limiter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Number of requests per second allowed.
+    pub requests_per_second: f64,
+    /// Number of requests that can be sent in a burst.
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub fn new(requests_per_second: f64, burst: Option<u32>) -> Self {
+        Self {
+            requests_per_second,
+            // by default allow a burst of one second of requests
+            burst: burst.unwrap_or(requests_per_second.ceil() as u32).max(1),
+        }
+    }
+}
+
+struct BucketState {
+    tokens: f64,
+    last_refill: Instant,
+    /// Do not send any request before this instant,
+    /// set by the `Retry-After` header.
+    paused_until: Option<Instant>,
+}
+
+/// Token bucket rate limiter, when `limit` is `None`
+/// only the `Retry-After` pause is applied.
+pub struct TokenBucket {
+    limit: Option<RateLimit>,
+    max_pause: Duration,
+    state: Mutex<BucketState>,
+}
+
+impl TokenBucket {
+    pub fn new(limit: Option<RateLimit>, max_pause: Duration) -> Self {
+        let tokens = limit.map(|limit| limit.burst as f64).unwrap_or_default();
+        Self {
+            limit,
+            max_pause,
+            state: Mutex::new(BucketState {
+                tokens,
+                last_refill: Instant::now(),
+                paused_until: None,
+            }),
+        }
+    }
+
+    /// Stop sending requests for the `duration`, capped to `max_pause`.
+    pub fn pause(&self, duration: Duration) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let Some(until) = Instant::now().checked_add(duration.min(self.max_pause)) else {
+            return;
+        };
+        if state.paused_until.map_or(true, |paused| paused < until) {
+            state.paused_until = Some(until);
+        }
+    }
+
+    /// Return how long the caller must wait before sending
+    /// the request, and consume a token if it is available.
+    pub(crate) fn reserve(&self, now: Instant) -> Option<Duration> {
+        let Ok(mut state) = self.state.lock() else {
+            return None;
+        };
+        if let Some(paused_until) = state.paused_until {
+            if paused_until > now {
+                return Some(paused_until - now);
+            }
+            state.paused_until = None;
+        }
+        let limit = self.limit?;
+        let elapsed = now.saturating_duration_since(state.last_refill);
+        state.tokens = (state.tokens + elapsed.as_secs_f64() * limit.requests_per_second)
+            .min(limit.burst as f64);
+        state.last_refill = now;
+        if state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+            return None;
+        }
+        let missing = 1.0 - state.tokens;
+        Some(Duration::from_secs_f64(missing / limit.requests_per_second))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{RateLimit, TokenBucket, DEFAULT_MAX_PAUSE};
+
+    #[test]
+    fn test_burst_then_wait() {
+        let bucket = TokenBucket::new(Some(RateLimit::new(10.0, Some(2))), DEFAULT_MAX_PAUSE);
+        let now = Instant::now();
+        assert_eq!(bucket.reserve(now), None);
+        assert_eq!(bucket.reserve(now), None);
+        let wait = bucket.reserve(now);
+        assert_eq!(wait, Some(Duration::from_millis(100)));
+        // after 100 ms a new token is available
+        assert_eq!(bucket.reserve(now + Duration::from_millis(100)), None);
+    }
+
+    #[test]
+    fn test_pause() {
+        let bucket = TokenBucket::new(None, DEFAULT_MAX_PAUSE);
+        let now = Instant::now();
+        assert_eq!(bucket.reserve(now), None);
+        bucket.pause(Duration::from_secs(2));
+        let wait = bucket.reserve(now);
+        assert!(wait.is_some_and(|wait| wait > Duration::from_secs(1)));
+        assert_eq!(bucket.reserve(now + Duration::from_secs(3)), None);
+    }
+
+    #[test]
+    fn test_max_pause() {
+        let bucket = TokenBucket::new(None, Duration::from_secs(5));
+        let now = Instant::now();
+        // e.g: `Retry-After: 18446744073709551615`
+        bucket.pause(Duration::from_secs(u64::MAX));
+        let wait = bucket.reserve(now);
+        assert!(wait.is_some_and(|wait| wait <= Duration::from_secs(6)));
+        assert_eq!(bucket.reserve(now + Duration::from_secs(6)), None);
+    }
+}
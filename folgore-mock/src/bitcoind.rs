@@ -0,0 +1,269 @@
+//! Bitcoin core stand-in server.
+//!
+//! Serve the chain of a `MockBackend` with the bitcoin core
+//! JSON-RPC methods used by `folgore-bitcoind`, so the RPC
+//! client can be tested without a node. The values use the same
+//! units of bitcoin core (e.g: the feerates are in BTC/kvB), and
+//! the server can be told to answer with an HTTP status or with
+//! an RPC error code to test how the client reacts.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use base64::Engine;
+use serde_json::{json, Value};
+
+use crate::http::{HttpFaults, HttpServer, Response};
+use crate::{MockBackend, OutPoint};
+
+/// Satoshis in a bitcoin, the RPC amounts are in BTC.
+const COIN: f64 = 100_000_000.0;
+
+/// The error injected on an RPC method.
+#[derive(Clone, Copy, Debug)]
+enum RpcFault {
+    Status(u16),
+    Code(i64),
+}
+
+/// An RPC error of bitcoin core, with the code and the message.
+type RpcError = (i64, String);
+
+pub struct BitcoindServer {
+    server: HttpServer,
+    faults: Arc<Mutex<HttpFaults<RpcFault>>>,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl BitcoindServer {
+    /// Start the server, the requests without the basic
+    /// authentication of `user` and `pass` are rejected
+    /// with a 401 like bitcoin core does.
+    pub fn start(mock: Arc<MockBackend>, user: &str, pass: &str) -> io::Result<Self> {
+        let faults = Arc::new(Mutex::new(HttpFaults::default()));
+        let requests = Arc::new(Mutex::new(vec![]));
+        let auth = format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode(format!("{user}:{pass}"))
+        );
+        let server = {
+            let faults = faults.clone();
+            let requests = requests.clone();
+            HttpServer::start(move |request| {
+                if request.header("Authorization") != Some(auth.as_str()) {
+                    return Response::new(401, "");
+                }
+                let Ok(body) = serde_json::from_slice::<Value>(&request.body) else {
+                    return rpc_response(&Value::Null, Err((-32700, "Parse error".to_owned())));
+                };
+                let id = body.get("id").cloned().unwrap_or_default();
+                let method = body
+                    .get("method")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_owned();
+                if let Ok(mut requests) = requests.lock() {
+                    requests.push(method.clone());
+                }
+                let fault = faults
+                    .lock()
+                    .ok()
+                    .and_then(|mut faults| faults.take(&method));
+                match fault {
+                    Some(RpcFault::Status(status)) => Response::new(status, "injected error"),
+                    Some(RpcFault::Code(code)) => {
+                        rpc_response(&id, Err((code, "injected error".to_owned())))
+                    }
+                    None => {
+                        let params = body.get("params").cloned().unwrap_or(json!([]));
+                        rpc_response(&id, route(&mock, &method, &params))
+                    }
+                }
+            })?
+        };
+        Ok(Self {
+            server,
+            faults,
+            requests,
+        })
+    }
+
+    /// The url to give to the RPC client.
+    pub fn url(&self) -> String {
+        self.server.url()
+    }
+
+    /// Answer the next `times` calls to `method` with
+    /// the HTTP `status` code.
+    pub fn fail(&self, method: &str, status: u16, times: usize) {
+        if let Ok(mut faults) = self.faults.lock() {
+            faults.add(method, RpcFault::Status(status), times);
+        }
+    }
+
+    /// Answer the next `times` calls to `method` with
+    /// the RPC error `code`, e.g: -28 while the node is warming up.
+    pub fn fail_rpc(&self, method: &str, code: i64, times: usize) {
+        if let Ok(mut faults) = self.faults.lock() {
+            faults.add(method, RpcFault::Code(code), times);
+        }
+    }
+
+    /// The RPC methods called, e.g: `getblockcount`.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests
+            .lock()
+            .map(|requests| requests.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Build the JSON-RPC response, bitcoin core answers to
+/// an error with a 500 (or a 404 for an unknown method).
+fn rpc_response(id: &Value, result: Result<Value, RpcError>) -> Response {
+    match result {
+        Ok(result) => Response::json(200, &json!({ "result": result, "error": null, "id": id })),
+        Err((code, message)) => {
+            let status = if code == -32601 { 404 } else { 500 };
+            Response::json(
+                status,
+                &json!({
+                    "result": null,
+                    "error": { "code": code, "message": message },
+                    "id": id,
+                }),
+            )
+        }
+    }
+}
+
+fn param(params: &Value, idx: usize) -> Result<&Value, RpcError> {
+    params
+        .get(idx)
+        .ok_or((-1, format!("missing parameter at position {idx}")))
+}
+
+fn to_btc(sat: u64) -> f64 {
+    sat as f64 / COIN
+}
+
+fn route(mock: &MockBackend, method: &str, params: &Value) -> Result<Value, RpcError> {
+    let mut state = mock.state();
+    let tip = state.chain.tip();
+    let best_block_hash = state
+        .chain
+        .block(tip)
+        .map(|block| block.hash.clone())
+        .unwrap_or_default();
+    match method {
+        "getblockchaininfo" => Ok(json!({
+            "chain": state.network,
+            "blocks": tip,
+            "headers": tip,
+            "bestblockhash": best_block_hash,
+            "difficulty": 4.656542373906925e-10,
+            "mediantime": 0,
+            "verificationprogress": 1.0,
+            "initialblockdownload": state.ibd,
+            "chainwork": format!("{:064x}", (tip + 1) * 2),
+            "size_on_disk": 0,
+            "pruned": false,
+            "warnings": "",
+        })),
+        // the RPC client checks the version to decode the chain info
+        "getnetworkinfo" => Ok(json!({
+            "version": 250000,
+            "subversion": "/Satoshi:25.0.0/",
+            "protocolversion": 70016,
+            "networkactive": true,
+            "connections": 0,
+            "relayfee": to_btc(state.fees.floor),
+            "warnings": "",
+        })),
+        "getblockcount" => Ok(json!(tip)),
+        "getblockhash" => {
+            let block = param(params, 0)?
+                .as_u64()
+                .and_then(|height| state.chain.block(height));
+            match block {
+                Some(block) => Ok(json!(block.hash)),
+                None => Err((-8, "Block height out of range".to_owned())),
+            }
+        }
+        "getblock" => {
+            let hash = param(params, 0)?.as_str().unwrap_or_default();
+            let verbosity = params.get(1).and_then(Value::as_u64).unwrap_or(1);
+            if verbosity != 0 {
+                return Err((-8, "only the verbosity 0 is served".to_owned()));
+            }
+            match state.chain.block_by_hash(hash) {
+                Some(block) => Ok(json!(block.raw)),
+                None => Err((-5, "Block not found".to_owned())),
+            }
+        }
+        "getmempoolinfo" => {
+            // the floor is in sat/kvB
+            let floor = to_btc(state.fees.floor);
+            Ok(json!({
+                "loaded": true,
+                "size": 0,
+                "bytes": 0,
+                "usage": 0,
+                "maxmempool": 300_000_000,
+                "mempoolminfee": floor,
+                "minrelaytxfee": floor,
+            }))
+        }
+        "estimatesmartfee" => {
+            let target = param(params, 0)?.as_u64().unwrap_or_default();
+            // like bitcoin core, answer with the first estimate
+            // that is able to confirm in `target` blocks
+            let fee = state
+                .fees
+                .feerates
+                .iter()
+                .find(|fee| fee.blocks >= target)
+                .or(state.fees.feerates.last());
+            match fee {
+                Some(fee) => Ok(json!({ "feerate": to_btc(fee.feerate), "blocks": fee.blocks })),
+                None => Ok(json!({
+                    "errors": ["Insufficient data or no feerate found"],
+                    "blocks": 0,
+                })),
+            }
+        }
+        "gettxout" => {
+            let txid = param(params, 0)?.as_str().unwrap_or_default();
+            let vout = param(params, 1)?.as_u64().unwrap_or_default();
+            let Some(output) = state.chain.utxo(&OutPoint::new(txid, vout)) else {
+                return Ok(Value::Null);
+            };
+            // the height of the outputs is not tracked, so they
+            // are reported as confirmed in the tip
+            Ok(json!({
+                "bestblock": best_block_hash,
+                "confirmations": 1,
+                "value": to_btc(output.amount),
+                "scriptPubKey": { "asm": "", "hex": output.script },
+                "coinbase": false,
+            }))
+        }
+        "sendrawtransaction" => {
+            let tx = param(params, 0)?.as_str().unwrap_or_default();
+            state
+                .chain
+                .broadcast(tx)
+                .map(|txid| json!(txid))
+                .map_err(|err| {
+                    let code = match err.as_str() {
+                        "TX decode failed" => -22,
+                        "txn-already-known" => -27,
+                        _ => -25,
+                    };
+                    (code, err)
+                })
+        }
+        _ => Err((-32601, "Method not found".to_owned())),
+    }
+}
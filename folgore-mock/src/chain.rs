@@ -0,0 +1,324 @@
+//! Synthetic chain kept in memory by the mock backend.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::collections::{HashMap, HashSet};
+
+use folgore_common::client::UtxoOut;
+use folgore_common::fixture::{block, regtest_genesis, varint, COINBASE_AMOUNT, COINBASE_SCRIPT};
+use folgore_common::fixture::{Fixture, FixtureBlock, FixtureOutput};
+use folgore_common::utils::bitcoin_hashes::hex::FromHex;
+use folgore_common::utils::bitcoin_hashes::{sha256d, Hash};
+use folgore_common::utils::ByteBuf;
+
+use crate::OutPoint;
+
+/// A transaction accepted by the chain.
+#[derive(Clone, Debug)]
+struct MockTx {
+    txid: String,
+    raw: Vec<u8>,
+    inputs: Vec<OutPoint>,
+    outputs: Vec<UtxoOut>,
+}
+
+#[derive(Clone, Debug)]
+struct MockBlock {
+    block: FixtureBlock,
+    txs: Vec<MockTx>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct MockChain {
+    blocks: Vec<MockBlock>,
+    /// The outputs created by the blocks (or by hand).
+    outputs: HashMap<OutPoint, UtxoOut>,
+    /// The outputs spent by a confirmed or by a mempool transaction.
+    spent: HashSet<OutPoint>,
+    mempool: Vec<MockTx>,
+    /// Used to build a different block at the same height.
+    nonce: u32,
+    /// Used to build the txid of the outputs created by hand.
+    counter: u64,
+}
+
+impl MockChain {
+    /// Build a chain of `blocks` blocks on top of the
+    /// regtest genesis block.
+    pub fn new(blocks: u64) -> Self {
+        let mut chain = Self::default();
+        chain.connect(MockBlock {
+            block: regtest_genesis(),
+            txs: vec![],
+        });
+        chain.mine(blocks.saturating_sub(1));
+        chain
+    }
+
+    /// Load the chain of the conformance fixture.
+    pub fn from_fixture(fixture: &Fixture) -> Self {
+        let mut chain = Self::default();
+        for block in &fixture.blocks {
+            chain.connect(MockBlock {
+                block: block.clone(),
+                txs: vec![],
+            });
+        }
+        chain.spent.insert(OutPoint::from(&fixture.spent));
+        chain
+    }
+
+    pub fn tip(&self) -> u64 {
+        self.blocks.len() as u64 - 1
+    }
+
+    pub fn block(&self, height: u64) -> Option<&FixtureBlock> {
+        self.blocks
+            .get(height as usize)
+            .map(|mock_block| &mock_block.block)
+    }
+
+    pub fn block_by_hash(&self, hash: &str) -> Option<&FixtureBlock> {
+        self.blocks
+            .iter()
+            .map(|mock_block| &mock_block.block)
+            .find(|block| block.hash == hash)
+    }
+
+    pub fn utxo(&self, outpoint: &OutPoint) -> Option<UtxoOut> {
+        if self.spent.contains(outpoint) {
+            return None;
+        }
+        self.outputs.get(outpoint).cloned()
+    }
+
+    pub fn is_spent(&self, outpoint: &OutPoint) -> bool {
+        self.spent.contains(outpoint)
+    }
+
+    /// The outputs of the transaction, spent or not, and whether
+    /// the transaction is confirmed. The transactions are known
+    /// only by their outputs, so the outputs created by hand are
+    /// returned as a confirmed transaction.
+    pub fn transaction(&self, txid: &str) -> Option<(Vec<UtxoOut>, bool)> {
+        if let Some(tx) = self.mempool.iter().find(|tx| tx.txid == txid) {
+            return Some((tx.outputs.clone(), false));
+        }
+        let mut outputs = self
+            .outputs
+            .iter()
+            .filter(|(outpoint, _)| outpoint.txid == txid)
+            .map(|(outpoint, output)| (outpoint.vout, output.clone()))
+            .collect::<Vec<_>>();
+        if outputs.is_empty() {
+            return None;
+        }
+        outputs.sort_by_key(|(vout, _)| *vout);
+        Some((
+            outputs.into_iter().map(|(_, output)| output).collect(),
+            true,
+        ))
+    }
+
+    /// Mine `count` blocks that confirm the mempool, and
+    /// return the hash of the new blocks.
+    pub fn mine(&mut self, count: u64) -> Vec<String> {
+        let mut hashes = vec![];
+        for _ in 0..count {
+            let txs = std::mem::take(&mut self.mempool);
+            let raw_txs = txs.iter().map(|tx| tx.raw.clone()).collect::<Vec<_>>();
+            let prev = self.blocks.last().map(|prev| prev.block.hash.as_str());
+            let block = block(self.blocks.len() as u64, prev, self.nonce, &raw_txs);
+            hashes.push(block.hash.clone());
+            self.connect(MockBlock { block, txs });
+        }
+        hashes
+    }
+
+    /// Replace the last `depth` blocks with `count` new blocks, the
+    /// transactions of the disconnected blocks are moved back in
+    /// the mempool and confirmed again by the new blocks.
+    pub fn reorg(&mut self, depth: u64, count: u64) -> Vec<String> {
+        // the genesis block is never disconnected
+        let depth = depth.min(self.tip());
+        for _ in 0..depth {
+            let Some(disconnected) = self.blocks.pop() else {
+                break;
+            };
+            self.outputs
+                .remove(&OutPoint::new(&disconnected.block.coinbase_txid, 0));
+            let mut txs = disconnected.txs;
+            for tx in &txs {
+                for vout in 0..tx.outputs.len() {
+                    self.outputs.remove(&OutPoint::new(&tx.txid, vout as u64));
+                }
+            }
+            txs.append(&mut self.mempool);
+            self.mempool = txs;
+        }
+        self.nonce += 1;
+        self.mine(count)
+    }
+
+    /// Create an output that is not part of any block, e.g: to
+    /// fund a channel without mining.
+    pub fn create_output(&mut self, amount: u64, script: &str) -> FixtureOutput {
+        self.counter += 1;
+        let txid = sha256d::Hash::hash(&self.counter.to_le_bytes()).to_string();
+        let output = UtxoOut {
+            amount,
+            script: script.to_owned(),
+        };
+        self.outputs.insert(OutPoint::new(&txid, 0), output);
+        FixtureOutput {
+            txid,
+            vout: 0,
+            amount,
+            script: script.to_owned(),
+        }
+    }
+
+    /// Mark the output as spent, return false if the output
+    /// is unknown or already spent.
+    pub fn spend(&mut self, outpoint: OutPoint) -> bool {
+        self.outputs.contains_key(&outpoint) && self.spent.insert(outpoint)
+    }
+
+    /// Accept the transaction in the mempool if all the inputs
+    /// are unspent, and return the txid. The errors are the
+    /// same of bitcoin core.
+    pub fn broadcast(&mut self, tx: &str) -> Result<String, String> {
+        let Some(tx) = decode(tx) else {
+            return Err("TX decode failed".to_owned());
+        };
+        if self.mempool.iter().any(|pending| pending.txid == tx.txid)
+            || self.outputs.contains_key(&OutPoint::new(&tx.txid, 0))
+        {
+            return Err("txn-already-known".to_owned());
+        }
+        if tx.inputs.iter().any(|input| self.utxo(input).is_none()) {
+            return Err("bad-txns-inputs-missingorspent".to_owned());
+        }
+        let txid = tx.txid.clone();
+        self.spent.extend(tx.inputs.iter().cloned());
+        self.mempool.push(tx);
+        Ok(txid)
+    }
+
+    fn connect(&mut self, mock_block: MockBlock) {
+        // like bitcoin core, the genesis coinbase is not spendable
+        if mock_block.block.height > 0 {
+            self.outputs.insert(
+                OutPoint::new(&mock_block.block.coinbase_txid, 0),
+                UtxoOut {
+                    amount: COINBASE_AMOUNT,
+                    script: COINBASE_SCRIPT.to_owned(),
+                },
+            );
+        }
+        for tx in &mock_block.txs {
+            for (vout, output) in tx.outputs.iter().enumerate() {
+                self.outputs
+                    .insert(OutPoint::new(&tx.txid, vout as u64), output.clone());
+            }
+        }
+        self.blocks.push(mock_block);
+    }
+}
+
+/// Read a consensus encoded transaction.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn varint(&mut self) -> Option<u64> {
+        let value = match self.take(1)?[0] {
+            0xfd => u16::from_le_bytes(self.take(2)?.try_into().ok()?) as u64,
+            0xfe => self.u32()? as u64,
+            0xff => self.u64()?,
+            value => value as u64,
+        };
+        Some(value)
+    }
+
+    fn script(&mut self) -> Option<&'a [u8]> {
+        let len = self.varint()?;
+        self.take(usize::try_from(len).ok()?)
+    }
+}
+
+/// Decode the inputs and the outputs of the transaction, the
+/// txid is computed without the witness like bitcoin core does.
+fn decode(tx: &str) -> Option<MockTx> {
+    let raw = Vec::<u8>::from_hex(tx).ok()?;
+    let mut reader = Reader {
+        bytes: &raw,
+        pos: 0,
+    };
+    reader.u32()?;
+    let mut count = reader.varint()?;
+    let segwit = count == 0;
+    if segwit {
+        // the flag byte
+        reader.take(1)?;
+        count = reader.varint()?;
+    }
+    let body_start = reader.pos - varint(count).len();
+    let mut inputs = vec![];
+    for _ in 0..count {
+        let mut txid = reader.take(32)?.to_vec();
+        txid.reverse();
+        let vout = reader.u32()?;
+        reader.script()?;
+        reader.u32()?;
+        inputs.push(OutPoint::new(
+            &format!("{:02x}", ByteBuf(&txid)),
+            vout as u64,
+        ));
+    }
+    let mut outputs = vec![];
+    for _ in 0..reader.varint()? {
+        let amount = reader.u64()?;
+        let script = reader.script()?;
+        outputs.push(UtxoOut {
+            amount,
+            script: format!("{:02x}", ByteBuf(script)),
+        });
+    }
+    let body_end = reader.pos;
+    if segwit {
+        for _ in 0..count {
+            for _ in 0..reader.varint()? {
+                reader.script()?;
+            }
+        }
+    }
+    reader.u32()?;
+    if reader.pos != raw.len() || inputs.is_empty() || outputs.is_empty() {
+        return None;
+    }
+    let mut legacy = raw[..4].to_vec();
+    legacy.extend(&raw[body_start..body_end]);
+    legacy.extend(&raw[raw.len() - 4..]);
+    Some(MockTx {
+        txid: sha256d::Hash::hash(&legacy).to_string(),
+        raw,
+        inputs,
+        outputs,
+    })
+}
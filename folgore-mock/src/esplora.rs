@@ -0,0 +1,185 @@
+//! Esplora stand-in server.
+//!
+//! Serve the chain of a `MockBackend` with the esplora REST
+//! endpoints used by `folgore-esplora`, so the HTTP client can
+//! be tested without a public instance. The server can be told
+//! to answer with an error status (e.g: 404, 429 or 5xx) to
+//! test how the client reacts.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use serde_json::{json, Map, Value};
+
+use folgore_common::utils::bitcoin_hashes::hex::FromHex;
+
+use crate::http::{HttpFaults, HttpServer, Request, Response};
+use crate::{MockBackend, OutPoint};
+
+pub struct EsploraServer {
+    server: HttpServer,
+    faults: Arc<Mutex<HttpFaults>>,
+    requests: Arc<Mutex<Vec<String>>>,
+    /// Value of the `Retry-After` header of the 429 answers.
+    retry_after: Arc<Mutex<String>>,
+}
+
+impl EsploraServer {
+    pub fn start(mock: Arc<MockBackend>) -> io::Result<Self> {
+        let faults = Arc::new(Mutex::new(HttpFaults::default()));
+        let requests = Arc::new(Mutex::new(vec![]));
+        let retry_after = Arc::new(Mutex::new("0".to_owned()));
+        let server = {
+            let faults = faults.clone();
+            let requests = requests.clone();
+            let retry_after = retry_after.clone();
+            HttpServer::start(move |request| {
+                let key = format!("{} {}", request.method, request.path);
+                if let Ok(mut requests) = requests.lock() {
+                    requests.push(key);
+                }
+                let fault = faults
+                    .lock()
+                    .ok()
+                    .and_then(|mut faults| faults.take(&request.path));
+                match fault {
+                    Some(429) => {
+                        let retry_after = retry_after
+                            .lock()
+                            .map(|value| value.clone())
+                            .unwrap_or_default();
+                        Response::new(429, "Too Many Requests")
+                            .with_header("Retry-After", &retry_after)
+                    }
+                    Some(status) => Response::new(status, "injected error"),
+                    None => route(&mock, &request),
+                }
+            })?
+        };
+        Ok(Self {
+            server,
+            faults,
+            requests,
+            retry_after,
+        })
+    }
+
+    /// The url to give to the esplora client.
+    pub fn url(&self) -> String {
+        self.server.url()
+    }
+
+    /// Answer the next `times` requests with a path that
+    /// starts with `path` with the `status` code.
+    pub fn fail(&self, path: &str, status: u16, times: usize) {
+        if let Ok(mut faults) = self.faults.lock() {
+            faults.add(path, status, times);
+        }
+    }
+
+    /// Set the `Retry-After` header sent with the
+    /// 429 answers, by default `0`.
+    pub fn retry_after(&self, value: &str) {
+        if let Ok(mut retry_after) = self.retry_after.lock() {
+            *retry_after = value.to_owned();
+        }
+    }
+
+    /// The requests received, e.g: `GET /blocks/tip/height`.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests
+            .lock()
+            .map(|requests| requests.clone())
+            .unwrap_or_default()
+    }
+}
+
+fn not_found(msg: &str) -> Response {
+    Response::new(404, msg)
+}
+
+fn route(mock: &MockBackend, request: &Request) -> Response {
+    let state = mock.state();
+    let chain = &state.chain;
+    let path = request.path.trim_start_matches('/');
+    let parts = path.split('/').collect::<Vec<_>>();
+    match (request.method.as_str(), parts.as_slice()) {
+        ("GET", ["blocks", "tip", "height"]) => Response::ok(chain.tip().to_string()),
+        ("GET", ["block-height", height]) => {
+            let block = height.parse().ok().and_then(|height| chain.block(height));
+            match block {
+                Some(block) => Response::ok(block.hash.clone()),
+                None => not_found("Block not found"),
+            }
+        }
+        ("GET", ["block", hash, "raw"]) => {
+            let block = chain
+                .block_by_hash(hash)
+                .and_then(|block| Vec::<u8>::from_hex(&block.raw).ok());
+            match block {
+                Some(block) => Response::ok(block),
+                None => not_found("Block not found"),
+            }
+        }
+        ("GET", ["tx", txid]) => {
+            if Vec::<u8>::from_hex(txid).map(|txid| txid.len()) != Ok(32) {
+                return Response::new(400, "Invalid hex string");
+            }
+            let Some((outputs, confirmed)) = chain.transaction(txid) else {
+                return not_found("Transaction not found");
+            };
+            let vout = outputs
+                .iter()
+                .map(|output| json!({ "value": output.amount, "scriptpubkey": output.script }))
+                .collect::<Vec<_>>();
+            Response::json(
+                200,
+                &json!({ "txid": txid, "vout": vout, "status": { "confirmed": confirmed } }),
+            )
+        }
+        ("GET", ["tx", txid, "outspend", vout]) => {
+            let spent = vout
+                .parse()
+                .map(|vout| chain.is_spent(&OutPoint::new(txid, vout)))
+                .unwrap_or_default();
+            if !spent {
+                return Response::json(200, &json!({ "spent": false }));
+            }
+            // the spending transaction is not tracked, so it is
+            // reported as confirmed in the tip
+            Response::json(
+                200,
+                &json!({
+                    "spent": true,
+                    "status": { "confirmed": true, "block_height": chain.tip() },
+                }),
+            )
+        }
+        ("GET", ["fee-estimates"]) => {
+            // esplora answers in sat/vB
+            let fees = state
+                .fees
+                .feerates
+                .iter()
+                .map(|fee| (fee.blocks.to_string(), json!(fee.feerate as f64 / 1000.0)))
+                .collect::<Map<_, _>>();
+            Response::json(200, &Value::Object(fees))
+        }
+        ("POST", ["tx"]) => {
+            let tx = String::from_utf8_lossy(&request.body).trim().to_owned();
+            drop(state);
+            match mock.state().chain.broadcast(&tx) {
+                Ok(txid) => Response::ok(txid),
+                Err(err) => Response::new(
+                    400,
+                    format!(
+                        "sendrawtransaction RPC error: {}",
+                        json!({ "code": -25, "message": err })
+                    ),
+                ),
+            }
+        }
+        _ => not_found("Not Found"),
+    }
+}
@@ -0,0 +1,238 @@
+//! Minimal HTTP server used by the stand-in servers.
+//!
+//! Each connection is served by a background thread and
+//! it is kept alive, because some HTTP clients of the backends
+//! (e.g: the bitcoin RPC client) reuse the same socket for all
+//! the requests. The server is stopped when dropped.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// A request received by the server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new<B: Into<Vec<u8>>>(status: u16, body: B) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: body.into(),
+        }
+    }
+
+    pub fn ok<B: Into<Vec<u8>>>(body: B) -> Self {
+        Self::new(200, body)
+    }
+
+    pub fn json(status: u16, body: &serde_json::Value) -> Self {
+        Self::new(status, body.to_string()).with_header("Content-Type", "application/json")
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+}
+
+pub(crate) struct HttpServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl HttpServer {
+    /// Listen on a random local port and answer each
+    /// request with the `handler`.
+    pub fn start<H>(handler: H) -> io::Result<Self>
+    where
+        H: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        let handler = Arc::new(handler);
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let Ok(mut stream) = stream else {
+                        continue;
+                    };
+                    let handler = handler.clone();
+                    let stop = stop.clone();
+                    std::thread::spawn(move || {
+                        // a broken connection does not stop the server
+                        let _ = serve(&mut stream, handler.as_ref(), &stop);
+                    });
+                }
+            })
+        };
+        Ok(Self {
+            addr,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+}
+
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // wake up the listener, so it can see the stop flag
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn serve<H: Fn(Request) -> Response>(
+    stream: &mut TcpStream,
+    handler: &H,
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    loop {
+        let Some(request) = read_request(&mut reader)? else {
+            return Ok(());
+        };
+        // the server was dropped while the connection was idle
+        if stop.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let response = handler(request);
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Length: {}\r\n",
+            response.status,
+            reason(response.status),
+            response.body.len()
+        );
+        for (name, value) in &response.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(&response.body)?;
+        stream.flush()?;
+    }
+}
+
+/// Read the next request of the connection, `None` if
+/// the client closed the connection.
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid request",
+        ));
+    };
+    let (method, path) = (method.to_owned(), path.to_owned());
+
+    let mut headers = vec![];
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_owned(), value.trim().to_owned()));
+        }
+    }
+    let mut request = Request {
+        method,
+        path,
+        headers,
+        body: vec![],
+    };
+    let len = request
+        .header("Content-Length")
+        .and_then(|len| len.parse::<usize>().ok())
+        .unwrap_or_default();
+    request.body = vec![0; len];
+    reader.read_exact(&mut request.body)?;
+    Ok(Some(request))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+/// The error responses injected in a stand-in server, each
+/// fault matches the requests whose key starts with `prefix`.
+/// By default the fault is the HTTP status of the response.
+#[derive(Debug)]
+pub(crate) struct HttpFaults<T = u16> {
+    faults: Vec<(String, T, usize)>,
+}
+
+impl<T> Default for HttpFaults<T> {
+    fn default() -> Self {
+        Self { faults: vec![] }
+    }
+}
+
+impl<T: Copy> HttpFaults<T> {
+    pub fn add(&mut self, prefix: &str, fault: T, times: usize) {
+        self.faults.push((prefix.to_owned(), fault, times));
+    }
+
+    /// The fault to answer to the request with `key`, if any.
+    pub fn take(&mut self, key: &str) -> Option<T> {
+        let (_, fault, remaining) = self
+            .faults
+            .iter_mut()
+            .find(|(prefix, _, remaining)| *remaining > 0 && key.starts_with(prefix.as_str()))?;
+        *remaining -= 1;
+        Some(*fault)
+    }
+}
@@ -0,0 +1,432 @@
+//! Folgore mock implementation
+//!
+//! This is an implementation of the folgore backend that
+//! keeps a synthetic chain in memory, so it is possible to
+//! test the plugin (e.g: the failover between clients)
+//! without any network. The test can mine blocks, create and
+//! spend outputs, trigger a reorg, inject latency and errors
+//! on each method, and inspect the calls received.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+#![deny(clippy::unwrap_used)]
+pub mod bitcoind;
+mod chain;
+pub mod esplora;
+mod http;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use folgore_common::client::{
+    BackendKind, BlockFeeRate, BroadcastResult, ChainInfo, FeeEstimates, FolgoreBackend, Operation,
+    RawBlock, UtxoOut,
+};
+use folgore_common::errors::{ErrorKind, FolgoreError};
+use folgore_common::fixture::{Fixture, FixtureOutput};
+use folgore_common::stragegy::{RecoveryStrategy, Transient};
+
+use crate::chain::MockChain;
+
+/// Recovery strategy that returns the first error, so a
+/// test sees the error injected in the mock or in a server.
+pub struct NoRetry;
+
+impl RecoveryStrategy for NoRetry {
+    fn apply<T, E, F>(&self, cb: F) -> Result<T, E>
+    where
+        E: Transient + fmt::Debug,
+        F: Fn() -> Result<T, E>,
+    {
+        cb()
+    }
+}
+
+/// An output identified by txid and vout.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct OutPoint {
+    pub txid: String,
+    pub vout: u64,
+}
+
+impl OutPoint {
+    pub fn new(txid: &str, vout: u64) -> Self {
+        Self {
+            txid: txid.to_owned(),
+            vout,
+        }
+    }
+}
+
+impl From<&FixtureOutput> for OutPoint {
+    fn from(output: &FixtureOutput) -> Self {
+        Self::new(&output.txid, output.vout)
+    }
+}
+
+/// A call received by the mock, `params` are the
+/// same of the RPC method.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Call {
+    pub method: Operation,
+    pub params: Value,
+}
+
+/// The error injected on a method.
+#[derive(Clone, Debug)]
+struct Fault {
+    kind: ErrorKind,
+    /// How many calls are going to fail, `None` for all of them.
+    remaining: Option<usize>,
+}
+
+struct MockState {
+    chain: MockChain,
+    network: String,
+    ibd: bool,
+    fees: FeeEstimates,
+    latency: HashMap<Operation, Duration>,
+    faults: HashMap<Operation, Fault>,
+    calls: Vec<Call>,
+}
+
+pub struct MockBackend {
+    name: &'static str,
+    state: Mutex<MockState>,
+}
+
+impl MockBackend {
+    /// Build a regtest chain with `blocks` blocks.
+    pub fn new(blocks: u64) -> Self {
+        Self::with_chain(MockChain::new(blocks), "regtest")
+    }
+
+    /// Build the chain of the conformance fixture.
+    pub fn from_fixture(fixture: &Fixture) -> Self {
+        Self::with_chain(MockChain::from_fixture(fixture), &fixture.chain)
+    }
+
+    fn with_chain(chain: MockChain, network: &str) -> Self {
+        Self {
+            name: "mock",
+            state: Mutex::new(MockState {
+                chain,
+                network: network.to_owned(),
+                ibd: false,
+                fees: FeeEstimates {
+                    floor: 1000,
+                    feerates: [2, 6, 12, 100]
+                        .into_iter()
+                        .map(|blocks| BlockFeeRate {
+                            blocks,
+                            feerate: 1000 * (100 / blocks + 1),
+                        })
+                        .collect(),
+                },
+                latency: HashMap::new(),
+                faults: HashMap::new(),
+                calls: vec![],
+            }),
+        }
+    }
+
+    /// Change the name returned by `kind`, useful to tell
+    /// apart two mocks (e.g: the client and the fallback).
+    pub fn with_name(mut self, name: &'static str) -> Self {
+        self.name = name;
+        self
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        // a test that panics while holding the lock does
+        // not invalidate the chain
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn tip(&self) -> u64 {
+        self.state().chain.tip()
+    }
+
+    /// Mine `count` blocks and return their hashes, the
+    /// transactions in the mempool are confirmed.
+    pub fn mine(&self, count: u64) -> Vec<String> {
+        self.state().chain.mine(count)
+    }
+
+    /// Replace the last `depth` blocks with `count` new blocks,
+    /// and return the hashes of the new blocks.
+    pub fn reorg(&self, depth: u64, count: u64) -> Vec<String> {
+        self.state().chain.reorg(depth, count)
+    }
+
+    /// Create an unspent output outside the chain.
+    pub fn create_output(&self, amount: u64, script: &str) -> FixtureOutput {
+        self.state().chain.create_output(amount, script)
+    }
+
+    /// Spend the output, return false if it is unknown or already spent.
+    pub fn spend(&self, outpoint: OutPoint) -> bool {
+        self.state().chain.spend(outpoint)
+    }
+
+    pub fn set_fees(&self, fees: FeeEstimates) {
+        self.state().fees = fees;
+    }
+
+    pub fn set_ibd(&self, ibd: bool) {
+        self.state().ibd = ibd;
+    }
+
+    /// Wait `latency` before answering to `method`.
+    pub fn set_latency(&self, method: Operation, latency: Duration) {
+        self.state().latency.insert(method, latency);
+    }
+
+    /// Fail all the next calls to `method` with an error of `kind`.
+    pub fn fail(&self, method: Operation, kind: ErrorKind) {
+        self.inject(method, kind, None);
+    }
+
+    /// Fail the next `times` calls to `method` with an error of `kind`.
+    pub fn fail_times(&self, method: Operation, kind: ErrorKind, times: usize) {
+        self.inject(method, kind, Some(times));
+    }
+
+    fn inject(&self, method: Operation, kind: ErrorKind, remaining: Option<usize>) {
+        self.state()
+            .faults
+            .insert(method, Fault { kind, remaining });
+    }
+
+    /// Remove all the latency and the errors injected.
+    pub fn clear_faults(&self) {
+        let mut state = self.state();
+        state.latency.clear();
+        state.faults.clear();
+    }
+
+    /// The calls received, in order.
+    pub fn calls(&self) -> Vec<Call> {
+        self.state().calls.clone()
+    }
+
+    /// How many times `method` was called.
+    pub fn call_count(&self, method: Operation) -> usize {
+        self.state()
+            .calls
+            .iter()
+            .filter(|call| call.method == method)
+            .count()
+    }
+
+    pub fn clear_calls(&self) {
+        self.state().calls.clear();
+    }
+
+    /// Record the call and apply the faults injected, the
+    /// latency is waited without holding the lock.
+    fn call(&self, method: Operation, params: Value) -> Result<(), FolgoreError> {
+        let latency = {
+            let mut state = self.state();
+            state.calls.push(Call { method, params });
+            state.latency.get(&method).copied()
+        };
+        if let Some(latency) = latency {
+            std::thread::sleep(latency);
+        }
+        let mut state = self.state();
+        let Some(fault) = state.faults.get_mut(&method) else {
+            return Ok(());
+        };
+        let kind = fault.kind;
+        match fault.remaining.as_mut() {
+            Some(0) => return Ok(()),
+            Some(remaining) => *remaining -= 1,
+            None => {}
+        }
+        Err(
+            FolgoreError::new(kind, format!("injected error on `{method}`"))
+                .with_backend(self.kind()),
+        )
+    }
+}
+
+impl FolgoreBackend for MockBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Custom(self.name)
+    }
+
+    fn sync_chain_info(&self, last_height: Option<u64>) -> Result<ChainInfo, FolgoreError> {
+        self.call(Operation::ChainInfo, json!({ "last_height": last_height }))?;
+        let state = self.state();
+        Ok(ChainInfo {
+            chain: state.network.clone(),
+            header_count: state.chain.tip(),
+            block_count: state.chain.tip(),
+            ibd: state.ibd,
+        })
+    }
+
+    fn sync_estimate_fees(&self) -> Result<FeeEstimates, FolgoreError> {
+        self.call(Operation::EstimateFees, json!({}))?;
+        Ok(self.state().fees.clone())
+    }
+
+    fn sync_block_by_height(&self, height: u64) -> Result<Option<RawBlock>, FolgoreError> {
+        self.call(Operation::BlockByHeight, json!({ "height": height }))?;
+        let block = self.state().chain.block(height).map(|block| RawBlock {
+            hash: block.hash.clone(),
+            block: block.raw.clone(),
+        });
+        Ok(block)
+    }
+
+    fn sync_get_utxo(&self, txid: &str, vout: u64) -> Result<Option<UtxoOut>, FolgoreError> {
+        self.call(Operation::GetUtxo, json!({ "txid": txid, "vout": vout }))?;
+        Ok(self.state().chain.utxo(&OutPoint::new(txid, vout)))
+    }
+
+    fn sync_send_raw_transaction(
+        &self,
+        tx: &str,
+        allow_high_fee: bool,
+    ) -> Result<BroadcastResult, FolgoreError> {
+        self.call(
+            Operation::SendRawTransaction,
+            json!({ "tx": tx, "allowhighfees": allow_high_fee }),
+        )?;
+        let result = match self.state().chain.broadcast(tx) {
+            Ok(_) => BroadcastResult::success(),
+            Err(err) => BroadcastResult::failure(err),
+        };
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use folgore_common::conformance;
+    use folgore_common::fixture;
+    use folgore_common::utils::bitcoin_hashes::hex::FromHex;
+    use folgore_common::utils::ByteBuf;
+
+    use super::*;
+
+    /// A transaction spending `input` to an `OP_TRUE` output.
+    fn spend_tx(input: &FixtureOutput) -> String {
+        let mut txid = Vec::<u8>::from_hex(&input.txid).unwrap_or_default();
+        txid.reverse();
+        let mut tx = 2u32.to_le_bytes().to_vec();
+        tx.push(1);
+        tx.extend(txid);
+        tx.extend((input.vout as u32).to_le_bytes());
+        tx.extend([0, 0xff, 0xff, 0xff, 0xff]);
+        tx.push(1);
+        tx.extend((input.amount - 1000).to_le_bytes());
+        tx.extend([1, 0x51]);
+        tx.extend(0u32.to_le_bytes());
+        format!("{:02x}", ByteBuf(&tx))
+    }
+
+    #[test]
+    fn test_conformance() {
+        let fixture = Fixture::regtest(10);
+        conformance::assert_conformance(&MockBackend::from_fixture(&fixture), &fixture);
+    }
+
+    #[test]
+    fn test_chain() {
+        let mock = MockBackend::new(1);
+        let hashes = mock.mine(3);
+        assert_eq!(mock.tip(), 3);
+        let Ok(Some(block)) = mock.sync_block_by_height(3) else {
+            panic!("block 3 not found");
+        };
+        assert_eq!(block.hash, hashes[2]);
+
+        let output = mock.create_output(10_000, "51");
+        let tx = spend_tx(&output);
+        let Ok(result) = mock.sync_send_raw_transaction(&tx, false) else {
+            panic!("broadcast failed");
+        };
+        assert!(result.success);
+        assert!(matches!(mock.sync_get_utxo(&output.txid, 0), Ok(None)));
+        let Ok(result) = mock.sync_send_raw_transaction(&tx, false) else {
+            panic!("broadcast failed");
+        };
+        assert!(!result.success);
+
+        // the transaction is confirmed in the next block
+        mock.mine(1);
+        let Ok(Some(block)) = mock.sync_block_by_height(4) else {
+            panic!("block 4 not found");
+        };
+        assert!(block.block.ends_with(&tx));
+
+        // after a reorg the transaction is confirmed again
+        let old = block.hash;
+        let hashes = mock.reorg(2, 3);
+        assert_eq!(mock.tip(), 5);
+        let Ok(Some(block)) = mock.sync_block_by_height(3) else {
+            panic!("block 3 not found");
+        };
+        assert_eq!(block.hash, hashes[0]);
+        let Ok(Some(block)) = mock.sync_block_by_height(4) else {
+            panic!("block 4 not found");
+        };
+        assert_ne!(block.hash, old);
+        let Ok(Some(block)) = mock.sync_block_by_height(3) else {
+            panic!("block 3 not found");
+        };
+        assert!(block.block.ends_with(&tx));
+
+        let output = mock.create_output(10_000, "51");
+        assert!(mock.spend(OutPoint::from(&output)));
+        assert!(!mock.spend(OutPoint::from(&output)));
+        let Ok(Some(genesis)) = mock.sync_block_by_height(0) else {
+            panic!("genesis block not found");
+        };
+        assert_eq!(genesis.hash, fixture::REGTEST_GENESIS_HASH);
+    }
+
+    #[test]
+    fn test_faults() {
+        let mock = MockBackend::new(10).with_name("primary");
+        mock.fail_times(Operation::EstimateFees, ErrorKind::Network, 2);
+        for _ in 0..2 {
+            let Err(err) = mock.sync_estimate_fees() else {
+                panic!("the error is not injected");
+            };
+            assert_eq!(err.kind(), ErrorKind::Network);
+            assert_eq!(err.backend(), Some(BackendKind::Custom("primary")));
+        }
+        assert!(mock.sync_estimate_fees().is_ok());
+
+        mock.fail(Operation::GetUtxo, ErrorKind::RateLimited);
+        assert!(mock.sync_get_utxo("txid", 0).is_err());
+        mock.set_latency(Operation::ChainInfo, Duration::from_millis(50));
+        let start = Instant::now();
+        assert!(mock.sync_chain_info(Some(2)).is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        mock.clear_faults();
+        assert!(mock.sync_get_utxo("txid", 0).is_ok());
+
+        assert_eq!(mock.call_count(Operation::EstimateFees), 3);
+        assert_eq!(
+            mock.calls()[4],
+            Call {
+                method: Operation::ChainInfo,
+                params: json!({ "last_height": 2 }),
+            }
+        );
+        mock.clear_calls();
+        assert!(mock.calls().is_empty());
+    }
+}
@@ -1,9 +1,9 @@
 //! Backland client implementation for nakamoto
 #![deny(clippy::unwrap_used)]
 use std::cell::Cell;
-use std::fmt::Display;
 use std::net::TcpStream;
-use std::sync::Mutex;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
 
 use nakamoto_client::handle::Handle;
@@ -11,16 +11,14 @@ use nakamoto_common::bitcoin::consensus::{deserialize, serialize};
 use nakamoto_common::block::{Block, Height, Transaction};
 use nakamoto_net_poll::{Reactor, Waker};
 
-use folgore_common::client::FolgoreBackend;
-use folgore_common::cln::json_utils;
-use folgore_common::cln::plugin::error;
-use folgore_common::cln::plugin::errors::PluginError;
-use folgore_common::cln::plugin::plugin::Plugin;
-use folgore_common::prelude::cln_plugin::types::LogLevel;
-use folgore_common::prelude::json;
-use folgore_common::prelude::json::Value;
+use folgore_common::client::{
+    BackendConfig, BackendKind, BackendRegistry, BroadcastResult, Capabilities, ChainInfo,
+    FeeEstimates, FolgoreBackend, Operation, RawBlock, Support, UtxoOut,
+};
+use folgore_common::errors::FolgoreError;
+use folgore_common::prelude::log;
 use folgore_common::stragegy::RecoveryStrategy;
-use folgore_common::utils::{bitcoin_hashes, hex, ByteBuf};
+use folgore_common::utils::{from_hex, ByteBuf};
 use folgore_esplora::Esplora;
 
 pub use nakamoto_client::Config;
@@ -50,6 +48,34 @@ impl<R: RecoveryStrategy> Nakamoto<R> {
 
         Ok(client)
     }
+
+    /// Build the nakamoto backend from the configuration, the
+    /// esplora options are used by the esplora backend that
+    /// serves the fees and the utxos.
+    pub fn from_config(config: &BackendConfig, strategy: Arc<R>) -> Result<Self, FolgoreError> {
+        let network = Network::from_str(&config.network)
+            .map_err(|err| FolgoreError::unsupported(&err).with_backend(BackendKind::Nakamoto))?;
+        let nakamoto_config = Config {
+            network,
+            root: config.lightning_dir.clone().into(),
+            ..Default::default()
+        };
+        let esplora = Esplora::from_config(config, strategy)?;
+        Self::new(nakamoto_config, esplora)
+            .map_err(|err| FolgoreError::network(&err).with_backend(BackendKind::Nakamoto))
+    }
+}
+
+/// Register the nakamoto backend with the name `nakamoto`.
+pub fn register<R: RecoveryStrategy + 'static>(
+    registry: &mut BackendRegistry<R>,
+) -> Result<(), FolgoreError> {
+    registry.register(
+        "nakamoto",
+        folgore_esplora::options(),
+        Box::new(|config, strategy| Ok(Arc::new(Nakamoto::from_config(config, strategy)?))),
+    )?;
+    Ok(())
 }
 
 impl<R: RecoveryStrategy> Drop for Nakamoto<R> {
@@ -62,121 +88,116 @@ impl<R: RecoveryStrategy> Drop for Nakamoto<R> {
     }
 }
 
-fn from<T: Display>(err: T) -> PluginError {
-    error!("{err}")
+/// Decode the raw transaction sent by core lightning.
+pub fn decode_transaction(raw_tx: &str) -> Result<Transaction, FolgoreError> {
+    let tx = from_hex(raw_tx).map_err(|err| err.with_backend(BackendKind::Nakamoto))?;
+    deserialize(&tx).map_err(|err| {
+        FolgoreError::validation(format!("invalid transaction: {err}"))
+            .with_backend(BackendKind::Nakamoto)
+    })
 }
 
-impl<T: Clone, R: RecoveryStrategy> FolgoreBackend<T> for Nakamoto<R> {
-    fn kind(&self) -> folgore_common::client::BackendKind {
-        folgore_common::client::BackendKind::Nakamoto
+impl<R: RecoveryStrategy> FolgoreBackend for Nakamoto<R> {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Nakamoto
     }
 
-    fn sync_block_by_height(
-        &self,
-        plugin: &mut Plugin<T>,
-        height: u64,
-    ) -> Result<Value, PluginError> {
-        let mut response = json_utils::init_payload();
-        let header = self.handler.get_block_by_height(height).map_err(from)?;
-        let blk_chan = self.handler.blocks();
-        if header.is_none() {
-            return Ok(json::json!({
-                "blockhash": null,
-                "block": null,
-            }));
-        }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+            .with(
+                Operation::EstimateFees,
+                Support::Delegated(BackendKind::Esplora),
+            )
+            .with(Operation::GetUtxo, Support::Delegated(BackendKind::Esplora))
+    }
 
-        let header = header.ok_or(error!("header not found inside the block"))?;
+    fn sync_block_by_height(&self, height: u64) -> Result<Option<RawBlock>, FolgoreError> {
+        let header = self
+            .handler
+            .get_block_by_height(height)
+            .map_err(|err| FolgoreError::network(&err).with_backend(BackendKind::Nakamoto))?;
+        let blk_chan = self.handler.blocks();
+        let Some(header) = header else {
+            return Ok(None);
+        };
         self.handler
             .request_block(&header.block_hash())
-            .map_err(|err| error!("{err}"))?;
+            .map_err(|err| FolgoreError::network(&err).with_backend(BackendKind::Nakamoto))?;
         self.current_height
             .lock()
-            .map_err(|err| error!("{err}"))?
+            .map_err(|err| {
+                FolgoreError::invalid_response(&err).with_backend(BackendKind::Nakamoto)
+            })?
             .set(Some(height));
-        json_utils::add_str(
-            &mut response,
-            "blockhash",
-            header.block_hash().to_string().as_str(),
-        );
-
-        let (blk, _) = blk_chan.recv().map_err(|err| error!("{err}"))?;
+        let (blk, _) = blk_chan
+            .recv()
+            .map_err(|err| FolgoreError::network(err).with_backend(BackendKind::Nakamoto))?;
         let serialize = serialize(&blk);
-        let _: Block = deserialize(&serialize).map_err(|err| error!("{err}"))?;
+        let _: Block = deserialize(&serialize).map_err(|err| {
+            FolgoreError::invalid_response(&err).with_backend(BackendKind::Nakamoto)
+        })?;
         let ser_str = serialize.as_slice();
         let ser_str = format!("{:20x}", ByteBuf(ser_str));
-        plugin.log(LogLevel::Debug, "block by height: {ser_str}");
-        json_utils::add_str(&mut response, "block", &ser_str);
-        Ok(response)
+        log::debug!("block by height: {ser_str}");
+        Ok(Some(RawBlock {
+            hash: header.block_hash().to_string(),
+            block: ser_str,
+        }))
     }
 
-    fn sync_chain_info(
-        &self,
-        plugin: &mut Plugin<T>,
-        known_height: Option<u64>,
-    ) -> Result<Value, PluginError> {
-        let (mut height, ..) = self.handler.get_tip().map_err(|err| error!("{err}"))?;
+    fn sync_chain_info(&self, known_height: Option<u64>) -> Result<ChainInfo, FolgoreError> {
+        let (mut height, ..) = self
+            .handler
+            .get_tip()
+            .map_err(|err| FolgoreError::network(&err).with_backend(BackendKind::Nakamoto))?;
         let syncing = if let Some(known_height) = known_height {
             // Wait to sync :)
-            plugin.log(
-                LogLevel::Debug,
-                "nakamoto is out of sync, so we syncing it. It will take a time",
-            );
+            log::debug!("nakamoto is out of sync, so we syncing it. It will take a time");
             while known_height > height {
-                let (new_height, ..) = self.handler.get_tip().map_err(|err| error!("{err}"))?;
+                let (new_height, ..) = self.handler.get_tip().map_err(|err| {
+                    FolgoreError::network(&err).with_backend(BackendKind::Nakamoto)
+                })?;
                 height = new_height;
             }
             known_height > height
         } else {
             false
         };
-        let mut resp = json_utils::init_payload();
-        let height: i64 = height.try_into().map_err(|err| error!("{err}"))?;
-        json_utils::add_number(&mut resp, "headercount", height);
-        json_utils::add_number(&mut resp, "blockcount", height);
         let network = match self.network {
             Network::Mainnet => "main",
             Network::Testnet => "test",
             Network::Regtest => "regtest",
             Network::Signet => "signet",
         };
-        json_utils::add_str(&mut resp, "chain", network);
-        json_utils::add_bool(&mut resp, "ibd", syncing);
-        Ok(resp)
+        Ok(ChainInfo {
+            chain: network.to_owned(),
+            header_count: height,
+            block_count: height,
+            ibd: syncing,
+        })
     }
 
     // FIXME: we can use the neutrino API here that it is just a json
-    fn sync_estimate_fees(&self, plugin: &mut Plugin<T>) -> Result<Value, PluginError> {
-        self.esplora.sync_estimate_fees(plugin)
+    fn sync_estimate_fees(&self) -> Result<FeeEstimates, FolgoreError> {
+        self.esplora.sync_estimate_fees()
     }
 
-    fn sync_get_utxo(
-        &self,
-        plugin: &mut Plugin<T>,
-        txid: &str,
-        idx: u64,
-    ) -> Result<Value, PluginError> {
+    fn sync_get_utxo(&self, txid: &str, idx: u64) -> Result<Option<UtxoOut>, FolgoreError> {
         // Nakamoto could monitor this transaction if will get inside the
         // blockchain but it is not ready yet. So we are forwarding the job
         // to the esplora backend.
-        self.esplora.sync_get_utxo(plugin, txid, idx)
+        self.esplora.sync_get_utxo(txid, idx)
     }
 
     fn sync_send_raw_transaction(
         &self,
-        _: &mut Plugin<T>,
         tx: &str,
         _: bool,
-    ) -> Result<Value, PluginError> {
-        let tx = hex!(tx);
-        let tx: Transaction = deserialize(&tx).map_err(|err| error!("{err}"))?;
-        let mut resp = json_utils::init_payload();
-        if let Err(err) = self.handler.submit_transaction(tx) {
-            json_utils::add_bool(&mut resp, "success", false);
-            json_utils::add_str(&mut resp, "errmsg", &format!("{err}"));
-        } else {
-            json_utils::add_bool(&mut resp, "success", true);
+    ) -> Result<BroadcastResult, FolgoreError> {
+        let tx = decode_transaction(tx)?;
+        match self.handler.submit_transaction(tx) {
+            Ok(_) => Ok(BroadcastResult::success()),
+            Err(err) => Ok(BroadcastResult::failure(err)),
         }
-        Ok(resp)
     }
 }
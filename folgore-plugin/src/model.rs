@@ -1,6 +1,9 @@
-//! Rust model to unwrap the request send from core lightning
+//! Rust model to unwrap the request send from core lightning,
+//! the `bcli` responses are built by `folgore_common::client::bcli`.
 use serde::{Deserialize, Serialize};
 
+use folgore_common::client::{FolgoreBackend, Support};
+
 #[derive(Deserialize, Serialize)]
 pub struct BlockByHeight {
     pub(crate) height: u64,
@@ -27,3 +30,50 @@ pub(crate) struct GetChainInfo {
 pub(crate) struct DevUpdateUTxos {
     pub(crate) iamsure: bool,
 }
+
+/// How an operation is served by a client, `backend` is
+/// the client that serves the delegated operations.
+#[derive(Deserialize, Serialize, Debug)]
+pub(crate) struct CapabilityResponse {
+    pub(crate) method: String,
+    pub(crate) support: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) backend: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub(crate) struct BackendStatus {
+    pub(crate) backend: String,
+    pub(crate) capabilities: Vec<CapabilityResponse>,
+}
+
+impl From<&(dyn FolgoreBackend + Send + Sync)> for BackendStatus {
+    fn from(client: &(dyn FolgoreBackend + Send + Sync)) -> Self {
+        let capabilities = client
+            .capabilities()
+            .iter()
+            .map(|(op, support)| {
+                let (support, backend) = match support {
+                    Support::Native => ("native", None),
+                    Support::Delegated(kind) => ("delegated", Some(kind.to_string())),
+                    Support::Unsupported => ("unsupported", None),
+                };
+                CapabilityResponse {
+                    method: op.to_string(),
+                    support: support.to_owned(),
+                    backend,
+                }
+            })
+            .collect();
+        Self {
+            backend: client.kind().to_string(),
+            capabilities,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub(crate) struct StatusResponse {
+    pub(crate) client: BackendStatus,
+    pub(crate) fallback: Option<BackendStatus>,
+}
@@ -0,0 +1,161 @@
+//! Check that a client follows the network of core lightning.
+//!
+//! The network is checked in `init`, but the client may be not
+//! reachable yet (e.g: bitcoin core is still starting, or the
+//! client is retrying the request), in this case the client is
+//! wrapped in a `NetworkCheck` that checks the network at the
+//! first answer of the client, and refuses to serve the requests
+//! if the client is on another chain.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use serde_json::Value;
+
+use folgore_common::client::{
+    BackendKind, BroadcastResult, Capabilities, ChainInfo, FeeEstimates, FolgoreBackend, RawBlock,
+    UtxoOut,
+};
+use folgore_common::errors::FolgoreError;
+use folgore_common::prelude::log;
+
+/// A client that is not checked yet.
+pub(crate) struct NetworkCheck {
+    inner: Arc<dyn FolgoreBackend + Send + Sync>,
+    network: String,
+    expected: &'static str,
+    checked: AtomicBool,
+}
+
+impl NetworkCheck {
+    /// Check that `inner` is running on the `expected` chain,
+    /// the chain name of the core lightning `network`.
+    pub(crate) fn new(
+        inner: Arc<dyn FolgoreBackend + Send + Sync>,
+        network: &str,
+        expected: &'static str,
+    ) -> Self {
+        Self {
+            inner,
+            network: network.to_owned(),
+            expected,
+            checked: AtomicBool::new(false),
+        }
+    }
+
+    /// Check the chain returned by the client, the client is
+    /// checked only once it returns the expected chain.
+    fn check_chain(&self, chain: &str) -> Result<(), FolgoreError> {
+        check_chain(self.inner.kind(), chain, &self.network, self.expected)?;
+        if !self.checked.swap(true, Ordering::SeqCst) {
+            log::info!(
+                "client `{}` is running on the network `{}`",
+                self.inner.kind(),
+                self.network
+            );
+        }
+        Ok(())
+    }
+
+    /// Check the network before serving a request.
+    fn ensure_checked(&self) -> Result<(), FolgoreError> {
+        if self.checked.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let info = self.inner.sync_chain_info(None)?;
+        self.check_chain(&info.chain)
+    }
+}
+
+/// Return an error if the client is running on `chain`
+/// and core lightning on another one.
+pub(crate) fn check_chain(
+    kind: BackendKind,
+    chain: &str,
+    network: &str,
+    expected: &str,
+) -> Result<(), FolgoreError> {
+    if chain != expected {
+        return Err(FolgoreError::unsupported(format!(
+            "client `{kind}` is running on chain `{chain}` but core lightning is running on `{network}` (`{expected}`)"
+        ))
+        .with_backend(kind));
+    }
+    Ok(())
+}
+
+impl FolgoreBackend for NetworkCheck {
+    fn kind(&self) -> BackendKind {
+        self.inner.kind()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn sync_chain_info(&self, last_height: Option<u64>) -> Result<ChainInfo, FolgoreError> {
+        let info = self.inner.sync_chain_info(last_height)?;
+        self.check_chain(&info.chain)?;
+        Ok(info)
+    }
+
+    fn sync_estimate_fees(&self) -> Result<FeeEstimates, FolgoreError> {
+        self.ensure_checked()?;
+        self.inner.sync_estimate_fees()
+    }
+
+    fn sync_block_by_height(&self, height: u64) -> Result<Option<RawBlock>, FolgoreError> {
+        self.ensure_checked()?;
+        self.inner.sync_block_by_height(height)
+    }
+
+    fn sync_get_utxo(&self, txid: &str, idx: u64) -> Result<Option<UtxoOut>, FolgoreError> {
+        self.ensure_checked()?;
+        self.inner.sync_get_utxo(txid, idx)
+    }
+
+    fn sync_send_raw_transaction(
+        &self,
+        tx: &str,
+        allow_high_fee: bool,
+    ) -> Result<BroadcastResult, FolgoreError> {
+        self.ensure_checked()?;
+        self.inner.sync_send_raw_transaction(tx, allow_high_fee)
+    }
+
+    fn sync_dev_updateutxo(&self, iamsure: bool) -> Result<Value, FolgoreError> {
+        self.ensure_checked()?;
+        self.inner.sync_dev_updateutxo(iamsure)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use folgore_common::client::Operation;
+    use folgore_common::errors::ErrorKind;
+    use folgore_mock::MockBackend;
+
+    use super::*;
+
+    #[test]
+    fn test_network_check() {
+        let mock = Arc::new(MockBackend::new(5));
+        mock.fail_times(Operation::ChainInfo, ErrorKind::Network, 1);
+        let client = NetworkCheck::new(mock.clone(), "regtest", "regtest");
+        // the client is not reachable, so it is not checked
+        assert!(client.sync_estimate_fees().is_err());
+        assert!(client.sync_estimate_fees().is_ok());
+        assert!(client.sync_block_by_height(1).is_ok());
+        // the network is checked only once
+        assert_eq!(mock.call_count(Operation::ChainInfo), 2);
+
+        let client = NetworkCheck::new(mock, "bitcoin", "main");
+        for _ in 0..2 {
+            let err = client.sync_chain_info(None).err();
+            assert_eq!(err.map(|err| err.kind()), Some(ErrorKind::Unsupported));
+            let err = client.sync_estimate_fees().err();
+            assert_eq!(err.map(|err| err.kind()), Some(ErrorKind::Unsupported));
+        }
+    }
+}
@@ -1,52 +1,69 @@
 //! Plugin definition.
 
-use std::str::FromStr;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
 
 use clightningrpc_plugin_macros::plugin;
 use clightningrpc_plugin_macros::rpc_method;
 
+use serde_json as json;
 use serde_json::{json, Value};
 
-use folgore_bitcoind::BitcoinCore;
-use folgore_common::client::{BackendKind, FolgoreBackend};
+use folgore_common::client::fee_estimator::{FeeSmoother, SmoothingKind};
+use folgore_common::client::{
+    bcli, chain_name, trace, BackendConfig, BackendKind, BackendRegistry, FolgoreBackend,
+    Operation, RecordBackend, Support,
+};
 use folgore_common::cln::plugin::commands::{types::CLNConf, RPCCommand};
 use folgore_common::cln::plugin::error;
 use folgore_common::cln::plugin::errors::PluginError;
 use folgore_common::cln::plugin::plugin::Plugin;
 use folgore_common::cln::plugin::types::LogLevel;
-
-use folgore_esplora::Esplora;
-use folgore_nakamoto::{Config, Nakamoto, Network};
+use folgore_common::errors::{ErrorKind, FolgoreError};
+use folgore_common::recovery::{RetryConfig, RetryKind, TimeoutRetry};
 
 use crate::model::DevUpdateUTxos;
+use crate::model::{BackendStatus, StatusResponse};
 use crate::model::{BlockByHeight, GetChainInfo, GetUTxo, SendRawTx};
-use crate::recovery::TimeoutRetry;
+use crate::network::{self, NetworkCheck};
+
+/// How long `init` waits for the first answer of a client,
+/// then its network is checked at its first answer.
+const NETWORK_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(Clone)]
 pub struct PluginState {
-    pub(crate) client: Option<Arc<dyn FolgoreBackend<PluginState>>>,
-    pub(crate) fallback: Option<Arc<dyn FolgoreBackend<PluginState>>>,
-    pub(crate) esplora_url: Option<String>,
-    pub(crate) core_url: Option<String>,
-    pub(crate) core_user: Option<String>,
-    pub(crate) core_pass: Option<String>,
-    pub(crate) _retry_strategy: Option<String>,
+    pub(crate) client: Option<Arc<dyn FolgoreBackend + Send + Sync>>,
+    pub(crate) fallback: Option<Arc<dyn FolgoreBackend + Send + Sync>>,
+    /// The backends that the plugin is able to use.
+    pub(crate) registry: Arc<BackendRegistry<TimeoutRetry>>,
+    /// The value of the backend options.
+    pub(crate) backend_options: HashMap<String, Value>,
+    /// Retry configuration used by each backend.
+    pub(crate) retry_config: RetryConfig,
+    /// The trace file where the clients record their responses.
+    pub(crate) record_file: Option<String>,
+    /// Smooth the fees returned by the clients, one for the client
+    /// and one for the fallback client, so the history of a client
+    /// is not mixed with the fees of the other one. Empty if the
+    /// fees are not smoothed.
+    pub(crate) fee_smoothers: Vec<FeeSmoother>,
     /// CLN RPC path
     #[allow(dead_code)]
     cln_rpc_path: Option<String>,
 }
 
 impl PluginState {
-    fn new() -> Self {
+    fn new(registry: BackendRegistry<TimeoutRetry>) -> Self {
         PluginState {
             client: None,
             fallback: None,
-            esplora_url: None,
-            core_url: None,
-            core_pass: None,
-            core_user: None,
-            _retry_strategy: None,
+            registry: Arc::new(registry),
+            backend_options: HashMap::new(),
+            retry_config: RetryConfig::default(),
+            record_file: None,
+            fee_smoothers: vec![],
             cln_rpc_path: None,
         }
     }
@@ -55,60 +72,56 @@ impl PluginState {
         &self,
         client: &str,
         conf: &CLNConf,
-    ) -> Result<Arc<dyn FolgoreBackend<PluginState>>, PluginError> {
-        let client = BackendKind::try_from(client)?;
-        let rpc_path = format!("{}/{}", conf.lightning_dir, conf.rpc_file);
-        match client {
-            BackendKind::Nakamoto => {
-                let config = Config {
-                    network: Network::from_str(&conf.network).map_err(|err| error!("{err}"))?,
-                    root: conf.lightning_dir.clone().into(),
-                    ..Default::default()
-                };
-                let client = Esplora::new(
-                    &conf.network,
-                    self.esplora_url.to_owned(),
-                    TimeoutRetry::default().into(),
-                    &rpc_path,
-                )?;
-
-                let client = Nakamoto::new(config, client).map_err(|err| error!("{err}"))?;
-                Ok(Arc::new(client))
-            }
-            BackendKind::Esplora => {
-                // FIXME: check if there is the proxy enabled to pass the tor addrs
-                let client = Esplora::new(
-                    &conf.network,
-                    self.esplora_url.to_owned(),
-                    TimeoutRetry::default().into(),
-                    &rpc_path,
-                )?;
-                Ok(Arc::new(client))
-            }
-            BackendKind::BitcoinCore => {
-                let client = BitcoinCore::new(
-                    &self
-                        .core_url
-                        .clone()
-                        .ok_or(error!("bitcoin url not specified"))?,
-                    &self
-                        .core_user
-                        .clone()
-                        .ok_or(error!("bitcoin user not specified"))?,
-                    &self
-                        .core_pass
-                        .clone()
-                        .ok_or(error!("bitcoin pass not specied"))?,
-                )?;
-                Ok(Arc::new(client))
-            }
+    ) -> Result<Arc<dyn FolgoreBackend + Send + Sync>, PluginError> {
+        let config = BackendConfig {
+            network: conf.network.clone(),
+            lightning_dir: conf.lightning_dir.clone(),
+            rpc_path: format!("{}/{}", conf.lightning_dir, conf.rpc_file),
+            options: self.backend_options.clone(),
+        };
+        let client = client.trim();
+        if !self.registry.contains(client) && BackendKind::try_from(client).is_ok() {
+            return Err(FolgoreError::unsupported(format!(
+                "client `{client}` is not compiled in, please build the plugin with the `{client}` feature"
+            ))
+            .into());
         }
+        let strategy = TimeoutRetry::with_config(self.retry_config.clone()).into();
+        let client = self.registry.create(client, &config, strategy)?;
+        let Some(file) = self.record_file.as_ref() else {
+            return Ok(client);
+        };
+        let client = RecordBackend::new(client, trace::trace_path(&config, file))?;
+        Ok(Arc::new(client))
     }
 }
 
+/// The backends compiled inside the plugin (see the cargo
+/// features), a custom build can register here an out-of-tree
+/// backend.
+fn registry() -> Result<BackendRegistry<TimeoutRetry>, FolgoreError> {
+    #[allow(unused_mut)]
+    let mut registry = BackendRegistry::new();
+    #[cfg(feature = "nakamoto")]
+    folgore_nakamoto::register(&mut registry)?;
+    #[cfg(feature = "esplora")]
+    folgore_esplora::register(&mut registry)?;
+    #[cfg(feature = "bitcoind")]
+    folgore_bitcoind::register(&mut registry)?;
+    trace::register(&mut registry)?;
+    Ok(registry)
+}
+
 pub fn build_plugin() -> Plugin<PluginState> {
+    // SAFETY: the backends compiled inside the plugin have
+    // different names, otherwise it is a bug.
+    let registry = registry().expect("impossible register the backends, this is a bug");
+    let options = registry.options();
+    // the first backend compiled in is the default one
+    let default_client = registry.names().first().map(|name| name.to_string());
+    let default_fallback = registry.contains("esplora").then(|| "esplora".to_owned());
     let mut plugin = plugin! {
-        state: PluginState::new(),
+        state: PluginState::new(registry),
         dynamic: false,
         notification: [],
         methods: [
@@ -118,49 +131,167 @@ pub fn build_plugin() -> Plugin<PluginState> {
             getutxout,
             send_rawtransaction,
             dev_batch_utxupdate,
+            folgore_status,
         ],
         hooks: [],
     };
+    // the options of the backends compiled inside the plugin
+    for option in options {
+        plugin.add_opt(
+            option.name,
+            option.ty,
+            option.default.map(|value| value.to_owned()),
+            option.description,
+            false,
+        );
+    }
     plugin
         .add_opt(
-            "bitcoin-rpcpassword",
+            "bitcoin-client",
             "string",
-            None,
-            "Bitcoin RPC password",
+            default_client,
+            "Set up the client to use",
             false,
         )
-        .add_opt("bitcoin-rpcuser", "string", None, "Bitcoin RPC use", false)
         .add_opt(
-            "bitcoin-client",
+            "bitcoin-fallback-client",
             "string",
-            Some("nakamoto".to_owned()),
-            "Set up the client to use",
+            default_fallback,
+            "Set up the client to use in case of fallback client (by default `esplora`)",
             false,
         )
         .add_opt(
-            "bitcoin-rpcurl",
+            "bitcoin-record-file",
             "string",
             None,
-            "Set up the Bitcoin RPC URL",
+            "Record the responses of the clients in a trace, relative to the lightning dir, that can be served by the `replay` client",
             false,
         )
         .add_opt(
-            "bitcoin-fallback-client",
+            "bitcoin-fee-smoothing",
             "string",
-            Some("esplora".to_owned()),
-            "Set up the client to use in case of fallback client (by default `esplora`)",
+            Some("none".to_owned()),
+            "Smooth the fee estimates between two polls: `none`, `ewma` or `median` (by default `none`)",
+            false,
+        )
+        .add_opt(
+            "bitcoin-fee-smoothing-window",
+            "int",
+            None,
+            "Number of polls combined by the fee smoothing (by default 6)",
             false,
         )
         .add_opt(
-            "bitcoin-esplora-url",
+            "bitcoin-fee-max-change",
+            "int",
+            None,
+            "Maximum change in percent of a smoothed fee estimate between two polls (by default no limit)",
+            false,
+        )
+        .add_opt(
+            "bitcoin-retry-strategy",
             "string",
+            Some("exponential".to_owned()),
+            "Retry strategy used when a backend request fails: `none`, `fixed` or `exponential` (by default `exponential`), the retry options are shared by the client and the fallback client",
+            false,
+        )
+        .add_opt(
+            "bitcoin-retry-base-delay",
+            "int",
             None,
-            "A custom esplora backend url where to fetch the bitcoin data",
+            "Seconds to wait before retrying a failed request (by default 60)",
+            false,
+        )
+        .add_opt(
+            "bitcoin-retry-max-delay",
+            "int",
+            None,
+            "Maximum seconds to wait between two retries (by default 480)",
+            false,
+        )
+        .add_opt(
+            "bitcoin-retry-max-attempts",
+            "int",
+            None,
+            "Maximum number of retries of a failed request, between 0 and 255 (by default 4)",
+            false,
+        )
+        .add_opt(
+            "bitcoin-retry-deadline",
+            "int",
+            None,
+            "Maximum seconds spent retrying a single request (by default no deadline)",
             false,
         )
         .on_init(on_init)
 }
 
+/// Read an `int` option that can not be negative, an invalid
+/// value is an error instead of being replaced by the default.
+fn uint_opt(plugin: &Plugin<PluginState>, name: &str) -> Result<Option<u64>, PluginError> {
+    let value = match plugin.get_opt::<Value>(name) {
+        None | Some(Value::Null) => return Ok(None),
+        Some(value) => value,
+    };
+    let number = match &value {
+        Value::Number(number) => number.as_u64(),
+        Value::String(number) => number.trim().parse().ok(),
+        _ => None,
+    };
+    number.map(Some).ok_or(error!(
+        "`{name}` must be a positive number, found `{value}`"
+    ))
+}
+
+/// Read the retry configuration from the plugin options.
+fn retry_config(plugin: &Plugin<PluginState>) -> Result<RetryConfig, PluginError> {
+    let mut config = RetryConfig::default();
+    if let Some(kind) = plugin.get_opt::<String>("bitcoin-retry-strategy") {
+        config.kind = RetryKind::try_from(kind.trim())?;
+    }
+    if let Some(delay) = uint_opt(plugin, "bitcoin-retry-base-delay")? {
+        config.base_delay = Duration::from_secs(delay);
+    }
+    if let Some(delay) = uint_opt(plugin, "bitcoin-retry-max-delay")? {
+        config.max_delay = Duration::from_secs(delay);
+    }
+    if let Some(attempts) = uint_opt(plugin, "bitcoin-retry-max-attempts")? {
+        config.max_attempts = u8::try_from(attempts).map_err(|_| {
+            error!("`bitcoin-retry-max-attempts` must be between 0 and 255, found `{attempts}`")
+        })?;
+    }
+    if let Some(deadline) = uint_opt(plugin, "bitcoin-retry-deadline")? {
+        config.deadline = Some(Duration::from_secs(deadline));
+    }
+    if config.max_delay < config.base_delay {
+        return Err(error!(
+            "`bitcoin-retry-max-delay` must be greater than `bitcoin-retry-base-delay`"
+        ));
+    }
+    Ok(config)
+}
+
+/// Read the fee smoothing from the plugin options, `None`
+/// if the fees are returned as the clients estimate them.
+fn fee_smoother(plugin: &Plugin<PluginState>) -> Result<Option<FeeSmoother>, PluginError> {
+    // the options are checked also when the smoothing is disabled
+    let window = uint_opt(plugin, "bitcoin-fee-smoothing-window")?.unwrap_or(6);
+    let window = match usize::try_from(window) {
+        Ok(window) if window > 0 => window,
+        _ => {
+            return Err(error!(
+                "`bitcoin-fee-smoothing-window` must be at least 1, found `{window}`"
+            ))
+        }
+    };
+    let max_change = uint_opt(plugin, "bitcoin-fee-max-change")?;
+    let kind = match plugin.get_opt::<String>("bitcoin-fee-smoothing") {
+        Some(kind) if kind.trim() != "none" => SmoothingKind::try_from(kind.as_str())?,
+        _ => return Ok(None),
+    };
+    Ok(Some(FeeSmoother::new(kind, window, max_change)?))
+}
+
 // FIXME: on init should return an result where the error
 // is the reason of the disable
 fn on_init(plugin: &mut Plugin<PluginState>) -> Value {
@@ -168,27 +299,40 @@ fn on_init(plugin: &mut Plugin<PluginState>) -> Value {
         // if the client is not specified, set the esplora one as a default client
         .get_opt("bitcoin-client")
         .unwrap_or("esplora".to_owned());
-    if let Some(url) = plugin.get_opt::<String>("bitcoin-esplora-url") {
-        if !url.trim().is_empty() {
-            plugin.state.esplora_url = Some(url.trim().to_string());
+    let options = plugin.state.registry.options();
+    for option in options {
+        if let Some(value) = plugin.get_opt::<Value>(option.name) {
+            plugin
+                .state
+                .backend_options
+                .insert(option.name.to_owned(), value);
         }
     }
 
-    if let Some(url) = plugin.get_opt::<String>("bitcoin-rpcurl") {
-        if !url.trim().is_empty() {
-            plugin.state.core_url = Some(url);
-        }
-    }
+    plugin.state.record_file = plugin
+        .get_opt::<String>("bitcoin-record-file")
+        .filter(|file| !file.trim().is_empty());
 
-    if let Some(user) = plugin.get_opt::<String>("bitcoin-rpcuser") {
-        if !user.trim().is_empty() {
-            plugin.state.core_user = Some(user);
+    match retry_config(plugin) {
+        Ok(config) => plugin.state.retry_config = config,
+        Err(err) => {
+            return json!({
+                "disable": format!("{err}"),
+            })
         }
     }
 
-    if let Some(pass) = plugin.get_opt::<String>("bitcoin-rpcpassword") {
-        if !pass.trim().is_empty() {
-            plugin.state.core_pass = Some(pass);
+    match fee_smoother(plugin) {
+        Ok(smoother) => {
+            plugin.state.fee_smoothers = smoother
+                .into_iter()
+                .flat_map(|smoother| [smoother.clone(), smoother])
+                .collect()
+        }
+        Err(err) => {
+            return json!({
+                "disable": format!("{err}"),
+            })
         }
     }
 
@@ -198,7 +342,10 @@ fn on_init(plugin: &mut Plugin<PluginState>) -> Value {
         .configuration
         .clone()
         .expect("configuration is None, this is a bug inside the plugin API");
-    let client = plugin.state.new_client(&client, &conf);
+    let client = plugin
+        .state
+        .new_client(&client, &conf)
+        .and_then(|client| check_network(plugin, client, &conf.network));
     if let Err(err) = client {
         return json!({
             "disable": format!("{err}"),
@@ -208,7 +355,10 @@ fn on_init(plugin: &mut Plugin<PluginState>) -> Value {
 
     if let Some(fallback) = plugin.get_opt::<String>("bitcoin-fallback-client") {
         if !fallback.trim().is_empty() {
-            let client = plugin.state.new_client(&fallback, &conf);
+            let client = plugin
+                .state
+                .new_client(&fallback, &conf)
+                .and_then(|client| check_network(plugin, client, &conf.network));
             if let Err(err) = client {
                 return json!({
                     "disable": format!("{err}"),
@@ -221,41 +371,130 @@ fn on_init(plugin: &mut Plugin<PluginState>) -> Value {
     json!({})
 }
 
+/// Check that the backend is running on the same network
+/// of core lightning, otherwise return the reason to disable
+/// the plugin. A backend that does not answer within
+/// `NETWORK_PROBE_TIMEOUT` (e.g: it is retrying) is checked
+/// at its first answer, see `NetworkCheck`.
+fn check_network(
+    plugin: &mut Plugin<PluginState>,
+    client: Arc<dyn FolgoreBackend + Send + Sync>,
+    network: &str,
+) -> Result<Arc<dyn FolgoreBackend + Send + Sync>, PluginError> {
+    let Some(expected) = chain_name(network) else {
+        plugin.log(
+            LogLevel::Warn,
+            &format!(
+                "unknown network `{network}`, impossible check the network of client `{}`",
+                client.kind()
+            ),
+        );
+        return Ok(client);
+    };
+    let (sender, receiver) = mpsc::channel();
+    let probe = client.clone();
+    // the probe keeps running in background when it times out
+    std::thread::spawn(move || {
+        let _ = sender.send(probe.sync_chain_info(None));
+    });
+    let reason = match receiver.recv_timeout(NETWORK_PROBE_TIMEOUT) {
+        Ok(Ok(chain_info)) => {
+            network::check_chain(client.kind(), &chain_info.chain, network, expected)?;
+            return Ok(client);
+        }
+        Ok(Err(err)) => format!("{err}"),
+        Err(_) => format!("no answer in {} seconds", NETWORK_PROBE_TIMEOUT.as_secs()),
+    };
+    plugin.log(
+        LogLevel::Warn,
+        &format!(
+            "impossible check the network of client `{}`: {reason}, the network is checked at its first answer",
+            client.kind()
+        ),
+    );
+    Ok(Arc::new(NetworkCheck::new(client, network, expected)))
+}
+
+/// Run the request on the client, and on the fallback
+/// client if the first one fails, the clients that do not
+/// support the operation are skipped.
+fn dispatch<T, F>(plugin: &mut Plugin<PluginState>, op: Operation, cb: F) -> Result<T, PluginError>
+where
+    F: Fn(&dyn FolgoreBackend) -> Result<T, FolgoreError>,
+{
+    dispatch_client(plugin, op, cb).map(|(_, result)| result)
+}
+
+/// Like `dispatch`, but return also the position of the client
+/// that served the request, `0` for the client and `1` for the
+/// fallback client.
+fn dispatch_client<T, F>(
+    plugin: &mut Plugin<PluginState>,
+    op: Operation,
+    cb: F,
+) -> Result<(usize, T), PluginError>
+where
+    F: Fn(&dyn FolgoreBackend) -> Result<T, FolgoreError>,
+{
+    let client = plugin.state.client.clone().ok_or(error!(
+        "Client must be not null at this point, please report a bug"
+    ))?;
+    let fallback = plugin.state.fallback.clone();
+
+    let mut last_err = None;
+    for (idx, client) in [Some(client), fallback].into_iter().enumerate() {
+        let Some(client) = client else {
+            continue;
+        };
+        match client.capabilities().get(op) {
+            Support::Native => {}
+            Support::Delegated(to) => plugin.log(
+                LogLevel::Debug,
+                &format!("client `{}` delegates `{op}` to `{to}`", client.kind()),
+            ),
+            Support::Unsupported => {
+                plugin.log(
+                    LogLevel::Debug,
+                    &format!(
+                        "client `{}` does not support `{op}`, skipping",
+                        client.kind()
+                    ),
+                );
+                continue;
+            }
+        }
+        let err = match cb(client.as_ref()) {
+            Ok(result) => return Ok((idx, result)),
+            Err(err) if err.backend().is_none() => err.with_backend(client.kind()),
+            Err(err) => err,
+        };
+        plugin.log(
+            LogLevel::Warn,
+            &format!("client `{}` return an error: {err}", client.kind()),
+        );
+        // an invalid request is going to fail on the fallback client too
+        if err.kind() == ErrorKind::Validation {
+            return Err(err.into());
+        }
+        last_err = Some(err);
+    }
+    let err =
+        last_err.unwrap_or_else(|| FolgoreError::unsupported(format!("no client supports `{op}`")));
+    Err(err.into())
+}
+
 #[rpc_method(
     rpc_name = "getchaininfo",
     description = "getchaininfo to fetch information the data from the client"
 )]
 fn get_chain_info(plugin: &mut Plugin<PluginState>, request: Value) -> Result<Value, PluginError> {
     plugin.log(LogLevel::Debug, "call get chain info");
-    let mut plg = plugin.to_owned();
-    let client = plg.state.client.as_mut().ok_or(error!(
-        "Client must be not null at this point, please report a bug"
-    ))?;
-    // FIXME: make this not null
-    let fallback = plg.state.fallback.as_mut();
     plugin.log(LogLevel::Info, &format!("cln request {request}"));
     let request: GetChainInfo = serde_json::from_value(request)?;
-
-    let mut result: Result<Value, PluginError> = Err(error!("result undefined"));
-    for client in [Some(client), fallback] {
-        if client.is_none() {
-            continue;
-        }
-        let client = client.unwrap();
-        result = client.sync_chain_info(plugin, request.last_height.clone());
-        let Ok(ref result) = result else {
-            plugin.log(
-                LogLevel::Warn,
-                &format!(
-                    "client `{}` return an error: {}",
-                    client.kind(),
-                    result.clone().err().unwrap()
-                ),
-            );
-            continue;
-        };
-        break;
-    }
+    let result = dispatch(plugin, Operation::ChainInfo, |client| {
+        client.sync_chain_info(request.last_height)
+    })
+    .map(|info| bcli::chain_info(&info));
     plugin.log(LogLevel::Debug, &format!("{:?}", result));
     result
 }
@@ -266,32 +505,16 @@ fn get_chain_info(plugin: &mut Plugin<PluginState>, request: Value) -> Result<Va
 )]
 fn estimate_fees(plugin: &mut Plugin<PluginState>, _: Value) -> Result<Value, PluginError> {
     plugin.log(LogLevel::Debug, "call estimate fee info");
-    let client = plugin
-        .state
-        .client
-        .clone()
-        .ok_or(error!("client must be not null at this point"))?;
-    let fallback = plugin.state.fallback.clone();
-    let mut result: Result<Value, PluginError> = Err(error!("the result is null"));
-    for client in [Some(client), fallback] {
-        if client.is_none() {
-            continue;
-        }
-        let client = client.unwrap();
-        result = client.sync_estimate_fees(plugin);
-        let Ok(ref result) = result else {
-            plugin.log(
-                LogLevel::Warn,
-                &format!(
-                    "client `{}` return an error: {}",
-                    client.kind(),
-                    result.clone().err().unwrap()
-                ),
-            );
-            continue;
-        };
-        break;
-    }
+    let result = dispatch_client(plugin, Operation::EstimateFees, |client| {
+        client.sync_estimate_fees()
+    })
+    .map(
+        |(idx, fees)| match plugin.state.fee_smoothers.get_mut(idx) {
+            Some(smoother) => smoother.smooth(fees),
+            None => fees,
+        },
+    )
+    .map(|fees| bcli::estimate_fees(&fees));
     plugin.log(LogLevel::Debug, &format!("{:?}", result));
     result
 }
@@ -305,35 +528,12 @@ fn get_raw_block_by_height(
     request: Value,
 ) -> Result<Value, PluginError> {
     plugin.log(LogLevel::Debug, "call get block by height");
-    let client = plugin
-        .state
-        .client
-        .clone()
-        .ok_or(error!("Client must be null at this point"))?;
-    let fallback = plugin.state.fallback.clone();
     plugin.log(LogLevel::Info, &format!("cln request {request}"));
     let request: BlockByHeight = serde_json::from_value(request)?;
-    let mut result: Result<Value, PluginError> = Err(error!("result never init"));
-    for client in [Some(client), fallback] {
-        if client.is_none() {
-            continue;
-        }
-        let client = client.unwrap();
-        result = client.sync_block_by_height(plugin, request.height);
-        let Ok(ref result) = result else {
-            plugin.log(
-                LogLevel::Warn,
-                &format!(
-                    "client `{}` return an error: {}",
-                    client.kind(),
-                    result.clone().err().unwrap()
-                ),
-            );
-            continue;
-        };
-        break;
-    }
-    result
+    let block = dispatch(plugin, Operation::BlockByHeight, |client| {
+        client.sync_block_by_height(request.height)
+    })?;
+    Ok(bcli::raw_block(block.as_ref()))
 }
 
 #[rpc_method(
@@ -342,34 +542,12 @@ fn get_raw_block_by_height(
 )]
 fn getutxout(plugin: &mut Plugin<PluginState>, request: Value) -> Result<Value, PluginError> {
     plugin.log(LogLevel::Debug, "call get utxo");
-    let client = plugin
-        .state
-        .client
-        .clone()
-        .ok_or(error!("Client must be not null at this point"))?;
-    let fallback = plugin.state.fallback.clone();
     plugin.log(LogLevel::Info, &format!("cln request: {request}"));
     let request: GetUTxo = serde_json::from_value(request)?;
-    let mut result: Result<Value, PluginError> = Err(error!("result never init"));
-    for client in [Some(client), fallback] {
-        if client.is_none() {
-            continue;
-        }
-        let client = client.unwrap();
-        result = client.sync_get_utxo(plugin, &request.txid, request.vout);
-        let Ok(ref result) = result else {
-            plugin.log(
-                LogLevel::Warn,
-                &format!(
-                    "client `{}` return an error: {}",
-                    client.kind(),
-                    result.clone().err().unwrap()
-                ),
-            );
-            continue;
-        };
-        break;
-    }
+    let result = dispatch(plugin, Operation::GetUtxo, |client| {
+        client.sync_get_utxo(&request.txid, request.vout)
+    })
+    .map(|utxo| bcli::utxo(utxo.as_ref()));
     plugin.log(LogLevel::Debug, &format!("{:?}", result));
     result
 }
@@ -383,36 +561,12 @@ fn send_rawtransaction(
     request: Value,
 ) -> Result<Value, PluginError> {
     plugin.log(LogLevel::Debug, "call send raw transaction");
-    let client = plugin
-        .state
-        .client
-        .clone()
-        .ok_or(error!("Client must be not null at this point"))?;
-    let fallback = plugin.state.fallback.clone();
     plugin.log(LogLevel::Info, &format!("cln request: {request}"));
     let request: SendRawTx = serde_json::from_value(request)?;
-
-    let mut result: Result<Value, PluginError> = Err(error!("result never init"));
-    for client in [Some(client), fallback] {
-        if client.is_none() {
-            continue;
-        }
-        let client = client.unwrap();
-        result = client.sync_send_raw_transaction(plugin, &request.tx, request.allowhighfees);
-        let Ok(ref result) = result else {
-            plugin.log(
-                LogLevel::Warn,
-                &format!(
-                    "client `{}` return an error: {}",
-                    client.kind(),
-                    result.clone().err().unwrap()
-                ),
-            );
-            continue;
-        };
-        break;
-    }
-    result
+    let result = dispatch(plugin, Operation::SendRawTransaction, |client| {
+        client.sync_send_raw_transaction(&request.tx, request.allowhighfees)
+    })?;
+    Ok(bcli::send_raw_transaction(&result))
 }
 
 #[rpc_method(
@@ -424,34 +578,29 @@ fn dev_batch_utxupdate(
     request: Value,
 ) -> Result<Value, PluginError> {
     plugin.log(LogLevel::Debug, "call send raw transaction");
-    let client = plugin
-        .state
-        .client
-        .clone()
-        .ok_or(error!("Client must be not null at this point"))?;
-    let fallback = plugin.state.fallback.clone();
     plugin.log(LogLevel::Info, &format!("cln request: {request}"));
     let request: DevUpdateUTxos = serde_json::from_value(request)?;
+    dispatch(plugin, Operation::DevUpdateUtxo, |client| {
+        client.sync_dev_updateutxo(request.iamsure)
+    })
+}
 
-    let mut result: Result<Value, PluginError> = Err(error!("result never init"));
-    for client in [Some(client), fallback] {
-        if client.is_none() {
-            continue;
-        }
-        let client = client.unwrap();
-        result = client.sync_dev_updateutxo(plugin, request.iamsure);
-        let Ok(ref result) = result else {
-            plugin.log(
-                LogLevel::Warn,
-                &format!(
-                    "client `{}` return an error: {}",
-                    client.kind(),
-                    result.clone().err().unwrap()
-                ),
-            );
-            continue;
-        };
-        break;
-    }
-    result
+#[rpc_method(
+    rpc_name = "folgore-status",
+    description = "folgore-status to show the clients in use and the operations that they support"
+)]
+fn folgore_status(plugin: &mut Plugin<PluginState>, _: Value) -> Result<Value, PluginError> {
+    plugin.log(LogLevel::Debug, "call folgore status");
+    let client = plugin.state.client.clone().ok_or(error!(
+        "Client must be not null at this point, please report a bug"
+    ))?;
+    let status = StatusResponse {
+        client: BackendStatus::from(client.as_ref()),
+        fallback: plugin
+            .state
+            .fallback
+            .as_ref()
+            .map(|fallback| BackendStatus::from(fallback.as_ref())),
+    };
+    Ok(json::to_value(status)?)
 }
@@ -0,0 +1,473 @@
+//! End-to-end tests of the plugin binary.
+//!
+//! The tests launch `folgore_plugin` and drive it over stdio
+//! like core lightning does: `getmanifest`, then `init` with the
+//! options and the configuration, then the `bcli` methods. The
+//! clients talk with the stand-in servers of `folgore-mock`.
+//!
+//! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::io::{BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use folgore_common::client::{bcli, FolgoreBackend};
+use folgore_common::conformance::Fixture;
+use folgore_mock::bitcoind::BitcoindServer;
+use folgore_mock::esplora::EsploraServer;
+use folgore_mock::MockBackend;
+
+/// How long to wait for an answer of the plugin.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The plugin process, killed when dropped.
+struct PluginProcess {
+    child: Child,
+    stdin: ChildStdin,
+    /// The messages written by the plugin, responses
+    /// and notifications (e.g: the logs).
+    messages: Receiver<Value>,
+    id: u64,
+}
+
+impl PluginProcess {
+    fn start() -> Self {
+        let Ok(mut child) = Command::new(env!("CARGO_BIN_EXE_folgore_plugin"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            panic!("impossible launch the plugin");
+        };
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            panic!("the plugin stdio is not piped");
+        };
+        let (sender, messages) = mpsc::channel();
+        std::thread::spawn(move || {
+            // the messages are not always separated by a new line,
+            // so they are read as a stream of JSON values
+            let stream = serde_json::Deserializer::from_reader(BufReader::new(stdout));
+            for message in stream.into_iter::<Value>() {
+                let Ok(message) = message else {
+                    break;
+                };
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            child,
+            stdin,
+            messages,
+            id: 0,
+        }
+    }
+
+    /// Send the request and wait for its response, the
+    /// error is the JSON-RPC error returned by the plugin.
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, Value> {
+        self.id += 1;
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": self.id,
+            "method": method,
+            "params": params,
+        });
+        let sent = self
+            .stdin
+            .write_all(format!("{request}\n\n").as_bytes())
+            .and_then(|_| self.stdin.flush());
+        if let Err(err) = sent {
+            panic!("impossible send `{method}` to the plugin: {err}");
+        }
+        loop {
+            let Ok(message) = self.messages.recv_timeout(TIMEOUT) else {
+                panic!("the plugin does not answer to `{method}`");
+            };
+            // skip the notifications
+            if message.get("id") != Some(&json!(self.id)) {
+                continue;
+            }
+            if let Some(error) = message.get("error") {
+                return Err(error.clone());
+            }
+            return Ok(message.get("result").cloned().unwrap_or_default());
+        }
+    }
+
+    /// Run the handshake of core lightning and return
+    /// the result of `init`.
+    fn init(&mut self, network: &str, options: Value) -> Value {
+        let Ok(manifest) = self.call("getmanifest", json!({ "allow-deprecated-apis": false }))
+        else {
+            panic!("manifest not returned");
+        };
+        assert!(manifest["rpcmethods"].is_array());
+        let configuration = json!({
+            "lightning-dir": std::env::temp_dir().display().to_string(),
+            "rpc-file": "lightning-rpc",
+            "startup": true,
+            "network": network,
+            "feature_set": {
+                "init": "",
+                "node": "",
+                "channel": "",
+                "invoice": "",
+            },
+        });
+        let init = self.call(
+            "init",
+            json!({ "options": options, "configuration": configuration }),
+        );
+        let Ok(init) = init else {
+            panic!("init failed: {init:?}");
+        };
+        init
+    }
+
+    fn bcli(&mut self, method: &str, params: Value) -> Value {
+        match self.call(method, params) {
+            Ok(result) => result,
+            Err(err) => panic!("`{method}` failed: {err}"),
+        }
+    }
+}
+
+impl Drop for PluginProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn bitcoind_server(mock: &Arc<MockBackend>) -> BitcoindServer {
+    let Ok(server) = BitcoindServer::start(mock.clone(), "user", "pass") else {
+        panic!("impossible start the bitcoin core server");
+    };
+    server
+}
+
+fn esplora_server(mock: &Arc<MockBackend>) -> EsploraServer {
+    let Ok(server) = EsploraServer::start(mock.clone()) else {
+        panic!("impossible start the esplora server");
+    };
+    server
+}
+
+/// The options to use bitcoin core as client, and esplora as
+/// fallback client, without waiting between the retries.
+fn options(bitcoind: &BitcoindServer, esplora: &EsploraServer) -> Value {
+    json!({
+        "bitcoin-client": "bitcoind",
+        "bitcoin-rpcurl": bitcoind.url(),
+        "bitcoin-rpcuser": "user",
+        "bitcoin-rpcpassword": "pass",
+        "bitcoin-fallback-client": "esplora",
+        "bitcoin-esplora-url": esplora.url(),
+        "bitcoin-retry-strategy": "none",
+    })
+}
+
+#[test]
+fn test_manifest() {
+    let mut plugin = PluginProcess::start();
+    let Ok(manifest) = plugin.call("getmanifest", json!({})) else {
+        panic!("manifest not returned");
+    };
+    let names = |key: &str| {
+        manifest[key]
+            .as_array()
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|item| item["name"].as_str().map(|name| name.to_owned()))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    };
+    let methods = names("rpcmethods");
+    for method in [
+        "getchaininfo",
+        "estimatefees",
+        "getrawblockbyheight",
+        "getutxout",
+        "sendrawtransaction",
+    ] {
+        assert!(methods.contains(&method.to_owned()), "`{method}` missing");
+    }
+    let options = names("options");
+    for option in [
+        "bitcoin-client",
+        "bitcoin-fallback-client",
+        "bitcoin-rpcurl",
+    ] {
+        assert!(options.contains(&option.to_owned()), "`{option}` missing");
+    }
+}
+
+#[test]
+fn test_bcli_methods() {
+    let mock = Arc::new(MockBackend::new(5));
+    let (bitcoind, esplora) = (bitcoind_server(&mock), esplora_server(&mock));
+    let mut plugin = PluginProcess::start();
+    assert_eq!(
+        plugin.init("regtest", options(&bitcoind, &esplora)),
+        json!({})
+    );
+    // the network of the fallback client is checked in `init`
+    let checked = esplora.requests().len();
+
+    let Ok(info) = mock.sync_chain_info(None) else {
+        panic!("chain info not returned");
+    };
+    assert_eq!(
+        plugin.bcli("getchaininfo", json!({ "last_height": 0 })),
+        bcli::chain_info(&info)
+    );
+    let Ok(fees) = mock.sync_estimate_fees() else {
+        panic!("fees not returned");
+    };
+    assert_eq!(
+        plugin.bcli("estimatefees", json!({})),
+        bcli::estimate_fees(&fees)
+    );
+    let block = mock.sync_block_by_height(3).ok().flatten();
+    assert!(block.is_some());
+    assert_eq!(
+        plugin.bcli("getrawblockbyheight", json!({ "height": 3 })),
+        bcli::raw_block(block.as_ref())
+    );
+    assert_eq!(
+        plugin.bcli("getrawblockbyheight", json!({ "height": 5 })),
+        bcli::raw_block(None)
+    );
+
+    let output = mock.create_output(10_000, "0014aa");
+    let utxo = mock.sync_get_utxo(&output.txid, 0).ok().flatten();
+    assert!(utxo.is_some());
+    assert_eq!(
+        plugin.bcli("getutxout", json!({ "txid": output.txid, "vout": 0 })),
+        bcli::utxo(utxo.as_ref())
+    );
+    let utxo = plugin.bcli("getutxout", json!({ "txid": output.txid, "vout": 1 }));
+    assert_eq!(utxo, bcli::utxo(None));
+
+    // the transaction spends an output that does not exist
+    let tx = Fixture::regtest(3).rejected_tx;
+    let result = plugin.bcli(
+        "sendrawtransaction",
+        json!({ "tx": tx, "allowhighfees": false }),
+    );
+    assert_eq!(result["success"], json!(false));
+    assert!(result["errmsg"]
+        .as_str()
+        .is_some_and(|errmsg| !errmsg.is_empty()));
+
+    // all the requests are served by bitcoin core
+    assert_eq!(esplora.requests().len(), checked);
+    assert!(bitcoind
+        .requests()
+        .contains(&"getblockchaininfo".to_owned()));
+}
+
+#[test]
+fn test_failover() {
+    let mock = Arc::new(MockBackend::new(5));
+    let (bitcoind, esplora) = (bitcoind_server(&mock), esplora_server(&mock));
+    let mut plugin = PluginProcess::start();
+    assert_eq!(
+        plugin.init("regtest", options(&bitcoind, &esplora)),
+        json!({})
+    );
+
+    // bitcoin core is not able to answer anymore
+    for method in ["getblockchaininfo", "getblockcount"] {
+        bitcoind.fail(method, 503, usize::MAX);
+    }
+    let Ok(info) = mock.sync_chain_info(None) else {
+        panic!("chain info not returned");
+    };
+    assert_eq!(
+        plugin.bcli("getchaininfo", json!({ "last_height": 0 })),
+        bcli::chain_info(&info)
+    );
+    let block = mock.sync_block_by_height(4).ok().flatten();
+    assert_eq!(
+        plugin.bcli("getrawblockbyheight", json!({ "height": 4 })),
+        bcli::raw_block(block.as_ref())
+    );
+    assert!(esplora
+        .requests()
+        .contains(&"GET /blocks/tip/height".to_owned()));
+
+    // when both the clients fail the error is returned to core lightning
+    esplora.fail("/blocks/tip/height", 503, usize::MAX);
+    let Err(err) = plugin.call("getchaininfo", json!({ "last_height": 0 })) else {
+        panic!("the error is not returned");
+    };
+    assert!(err["message"].is_string());
+}
+
+#[test]
+fn test_disable() {
+    let mock = Arc::new(MockBackend::new(5));
+    let (bitcoind, esplora) = (bitcoind_server(&mock), esplora_server(&mock));
+
+    // core lightning and the client are on a different network
+    let mut plugin = PluginProcess::start();
+    let init = plugin.init("bitcoin", options(&bitcoind, &esplora));
+    assert!(
+        init["disable"]
+            .as_str()
+            .is_some_and(|reason| reason.contains("regtest")),
+        "{init}"
+    );
+
+    let mut options = options(&bitcoind, &esplora);
+    options["bitcoin-retry-strategy"] = json!("sometimes");
+    let mut plugin = PluginProcess::start();
+    let init = plugin.init("regtest", options);
+    assert!(init["disable"].is_string(), "{init}");
+
+    // an invalid option is not replaced by the default value
+    for (name, value) in [
+        ("bitcoin-retry-max-attempts", json!(256)),
+        ("bitcoin-retry-max-attempts", json!(-1)),
+        ("bitcoin-retry-base-delay", json!(-1)),
+        ("bitcoin-retry-max-delay", json!("ten")),
+        ("bitcoin-retry-deadline", json!(-5)),
+        // lower than the default base delay
+        ("bitcoin-retry-max-delay", json!(10)),
+        ("bitcoin-fee-smoothing-window", json!(0)),
+        ("bitcoin-fee-smoothing-window", json!(-3)),
+        ("bitcoin-fee-max-change", json!(-1)),
+    ] {
+        let mut options = self::options(&bitcoind, &esplora);
+        options[name] = value;
+        let mut plugin = PluginProcess::start();
+        let init = plugin.init("regtest", options);
+        assert!(
+            init["disable"]
+                .as_str()
+                .is_some_and(|reason| reason.contains(name)),
+            "{init}"
+        );
+    }
+
+    let mut plugin = PluginProcess::start();
+    let init = plugin.init("regtest", json!({ "bitcoin-client": "unknown" }));
+    assert!(init["disable"].is_string(), "{init}");
+
+    // a network that can not be checked does not disable the plugin
+    let mut plugin = PluginProcess::start();
+    let init = plugin.init("unknown", self::options(&bitcoind, &esplora));
+    assert_eq!(init, json!({}));
+    assert!(plugin.call("getchaininfo", json!({})).is_ok());
+
+    // the client is not reachable in `init`, so the network
+    // is checked at its first answer
+    bitcoind.fail("getblockchaininfo", 503, 1);
+    let mut options = self::options(&bitcoind, &esplora);
+    options["bitcoin-fallback-client"] = json!("");
+    let mut plugin = PluginProcess::start();
+    assert_eq!(plugin.init("bitcoin", options), json!({}));
+    for method in ["getchaininfo", "estimatefees"] {
+        let Err(err) = plugin.call(method, json!({})) else {
+            panic!("`{method}` served on the wrong network");
+        };
+        assert!(
+            err["message"]
+                .as_str()
+                .is_some_and(|message| message.contains("regtest")),
+            "{err}"
+        );
+    }
+}
+
+#[test]
+fn test_fee_smoothing() {
+    let mock = Arc::new(MockBackend::new(5));
+    let (bitcoind, esplora) = (bitcoind_server(&mock), esplora_server(&mock));
+    let mut options = options(&bitcoind, &esplora);
+    options["bitcoin-fee-smoothing"] = json!("median");
+    options["bitcoin-fee-smoothing-window"] = json!(3);
+    let mut plugin = PluginProcess::start();
+    assert_eq!(plugin.init("regtest", options), json!({}));
+
+    let Ok(fees) = mock.sync_estimate_fees() else {
+        panic!("fees not returned");
+    };
+    for _ in 0..2 {
+        assert_eq!(
+            plugin.bcli("estimatefees", json!({})),
+            bcli::estimate_fees(&fees)
+        );
+    }
+    // a spike of a single poll does not reach core lightning
+    let mut spike = fees.clone();
+    for fee in spike.feerates.iter_mut() {
+        fee.feerate *= 10;
+    }
+    mock.set_fees(spike.clone());
+    assert_eq!(
+        plugin.bcli("estimatefees", json!({})),
+        bcli::estimate_fees(&fees)
+    );
+    assert_eq!(
+        plugin.bcli("estimatefees", json!({})),
+        bcli::estimate_fees(&spike)
+    );
+
+    let mut options = self::options(&bitcoind, &esplora);
+    options["bitcoin-fee-smoothing"] = json!("mean");
+    let mut plugin = PluginProcess::start();
+    let init = plugin.init("regtest", options);
+    assert!(init["disable"].is_string(), "{init}");
+}
+
+#[test]
+fn test_fee_smoothing_failover() {
+    let mock = Arc::new(MockBackend::new(5));
+    // esplora follows the same chain, with higher fees
+    let esplora_mock = Arc::new(MockBackend::new(5));
+    let Ok(mut esplora_fees) = esplora_mock.sync_estimate_fees() else {
+        panic!("fees not returned");
+    };
+    for fee in esplora_fees.feerates.iter_mut() {
+        fee.feerate *= 10;
+    }
+    esplora_mock.set_fees(esplora_fees);
+    let (bitcoind, esplora) = (bitcoind_server(&mock), esplora_server(&esplora_mock));
+    let mut options = options(&bitcoind, &esplora);
+    options["bitcoin-fee-smoothing"] = json!("median");
+    options["bitcoin-fee-smoothing-window"] = json!(3);
+    let mut plugin = PluginProcess::start();
+    assert_eq!(plugin.init("regtest", options), json!({}));
+
+    // the first poll is served by esplora
+    bitcoind.fail("getmempoolinfo", 503, 1);
+    let fallback = plugin.bcli("estimatefees", json!({}));
+    let Ok(fees) = mock.sync_estimate_fees() else {
+        panic!("fees not returned");
+    };
+    for _ in 0..2 {
+        assert_eq!(
+            plugin.bcli("estimatefees", json!({})),
+            bcli::estimate_fees(&fees)
+        );
+    }
+    assert_ne!(fallback, bcli::estimate_fees(&fees));
+    // after the failover the fees of bitcoin core are not
+    // mixed with the ones of esplora
+    bitcoind.fail("getmempoolinfo", 503, 1);
+    assert_eq!(plugin.bcli("estimatefees", json!({})), fallback);
+    assert_eq!(
+        plugin.bcli("estimatefees", json!({})),
+        bcli::estimate_fees(&fees)
+    );
+}
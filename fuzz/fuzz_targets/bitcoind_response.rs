@@ -0,0 +1,29 @@
+//! Map the responses of bitcoin core to folgore errors, and
+//! decode the raw transactions that it returns.
+#![no_main]
+use bitcoincore_rpc::jsonrpc::{self, Response};
+use libfuzzer_sys::fuzz_target;
+
+use folgore_bitcoind::fuzzing::{rpc_error, HttpsError};
+use folgore_common::errors::ErrorKind;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() >= 2 {
+        let code = u16::from_le_bytes([data[0], data[1]]);
+        let err = jsonrpc::Error::Transport(Box::new(HttpsError::Http(code)));
+        let _ = rpc_error(bitcoincore_rpc::Error::JsonRpc(err));
+    }
+    let Ok(response) = serde_json::from_slice::<Response>(data) else {
+        return;
+    };
+    match response.result::<String>() {
+        Ok(raw_tx) => {
+            if let Err(err) = folgore_bitcoind::decode_transaction(&raw_tx) {
+                assert_eq!(err.kind(), ErrorKind::Validation);
+            }
+        }
+        Err(err) => {
+            let _ = rpc_error(bitcoincore_rpc::Error::JsonRpc(err));
+        }
+    }
+});
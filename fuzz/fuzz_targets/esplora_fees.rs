@@ -0,0 +1,20 @@
+//! Build the fee estimates from the body of `/fee-estimates`.
+#![no_main]
+use std::collections::HashMap;
+
+use libfuzzer_sys::fuzz_target;
+
+use folgore_common::client::fee_estimator::FeeTargets;
+use folgore_esplora::parse;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(fee_rates) = serde_json::from_slice::<HashMap<String, f64>>(data) else {
+        return;
+    };
+    let targets = FeeTargets::default();
+    // the estimates can be null, but they are always returned
+    let Ok(fees) = parse::fee_estimates(&fee_rates, &targets) else {
+        panic!("fees not returned for {fee_rates:?}");
+    };
+    assert!(fees.feerates.is_empty() || fees.feerates.len() == targets.len());
+});
@@ -0,0 +1,18 @@
+//! Parse the bodies returned by an esplora server.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use folgore_esplora::parse;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse::height(data);
+    let _ = parse::block_hash(data.to_vec());
+    if let Ok(tx) = serde_json::from_slice::<parse::Tx>(data) {
+        for idx in [0, 1, u32::MAX as u64, u64::MAX] {
+            let _ = tx.output(idx);
+        }
+    }
+    if let Ok(outspend) = serde_json::from_slice::<parse::Outspend>(data) {
+        let _ = outspend.spent_height();
+    }
+});
@@ -0,0 +1,18 @@
+//! Decode the requests sent by core lightning to the plugin.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+// the plugin is a binary, so the model is included here
+#[allow(dead_code)]
+#[path = "../../folgore-plugin/src/model.rs"]
+mod model;
+
+use model::{BlockByHeight, DevUpdateUTxos, GetChainInfo, GetUTxo, SendRawTx};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = serde_json::from_slice::<BlockByHeight>(data);
+    let _ = serde_json::from_slice::<GetChainInfo>(data);
+    let _ = serde_json::from_slice::<GetUTxo>(data);
+    let _ = serde_json::from_slice::<SendRawTx>(data);
+    let _ = serde_json::from_slice::<DevUpdateUTxos>(data);
+});
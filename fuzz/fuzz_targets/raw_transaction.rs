@@ -0,0 +1,17 @@
+//! Decode the raw transaction received by `sendrawtransaction`.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use folgore_common::errors::ErrorKind;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(raw_tx) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Err(err) = folgore_bitcoind::decode_transaction(raw_tx) {
+        assert_eq!(err.kind(), ErrorKind::Validation);
+    }
+    if let Err(err) = folgore_nakamoto::decode_transaction(raw_tx) {
+        assert_eq!(err.kind(), ErrorKind::Validation);
+    }
+});
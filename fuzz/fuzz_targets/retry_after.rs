@@ -0,0 +1,22 @@
+//! Parse the `Retry-After` header sent by an esplora server.
+#![no_main]
+use std::time::Duration;
+
+use libfuzzer_sys::fuzz_target;
+
+use folgore_esplora::fuzzing::{parse_retry_after, TokenBucket};
+use folgore_esplora::DEFAULT_MAX_PAUSE;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(value) = std::str::from_utf8(data) else {
+        return;
+    };
+    let Some(retry_after) = parse_retry_after(value) else {
+        return;
+    };
+    // the header is controlled by the server, so any
+    // value must be accepted by the rate limiter.
+    for max_pause in [DEFAULT_MAX_PAUSE, Duration::MAX] {
+        TokenBucket::new(None, max_pause).pause(retry_after);
+    }
+});